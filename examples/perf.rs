@@ -24,4 +24,37 @@ fn main() {
     }
     let end = PreciseTime::now();
     println!("finished in {} ms", start.to(end).num_milliseconds());
+
+    bench_color::<MapBgraGray>("bgra -> gray", &Image::from_pixel(1920, 1080, Bgra([10, 20, 30, 255])));
+    bench_color::<MapGrayBgra>("gray -> bgra", &Image::from_pixel(1920, 1080, Gray([128])));
+}
+
+// compares the per-pixel mapper against the vectorized row path used by
+// `convert`
+fn bench_color<M: ColorMapper>(name: &str, src: &Image<M::SrcType>) {
+    let mut dst = Image::<M::DstType>::new(src.width(), src.height());
+    let width = src.width() as usize;
+    let start = PreciseTime::now();
+    for _ in 0..100 {
+        for y in 0..src.height() {
+            let psrc = src.row(y);
+            let pdst = dst.row_mut(y);
+            for x in 0..width {
+                pdst[x] = M::to(&psrc[x]);
+            }
+        }
+    }
+    let scalar = start.to(PreciseTime::now());
+    let check = dst.raw()[0];
+
+    let start = PreciseTime::now();
+    for _ in 0..100 {
+        for y in 0..src.height() {
+            M::map_row(&src.row(y)[..width], &mut dst.row_mut(y)[..width]);
+        }
+    }
+    let simd = start.to(PreciseTime::now());
+    assert!(dst.raw()[0] == check);
+    println!("{}: scalar {} ms, vectorized {} ms", name,
+             scalar.num_milliseconds(), simd.num_milliseconds());
 }
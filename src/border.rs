@@ -0,0 +1,114 @@
+//! Pixel extrapolation at the image borders, shared by the filters.
+
+use math::utils::clip;
+use traits::SaturateCast;
+
+/// How pixels outside of the image are read, shown for a row `abcdefgh`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BorderType {
+    /// `aaaaaa|abcdefgh|hhhhhhh`
+    Replicate,
+    /// `vvvvvv|abcdefgh|vvvvvvv`, applied to every channel
+    Constant(f32),
+    /// `fedcba|abcdefgh|hgfedcb`
+    Reflect,
+    /// `gfedcb|abcdefgh|gfedcba`
+    Reflect101,
+    /// `cdefgh|abcdefgh|abcdefg`
+    Wrap
+}
+
+impl Default for BorderType {
+    fn default() -> BorderType {
+        BorderType::Replicate
+    }
+}
+
+/// Maps coordinate `i` to `[0, len)`, `None` if it falls on a constant
+/// border.
+#[inline]
+pub fn border_index(i: i32, len: i32, border: BorderType) -> Option<i32> {
+    assert!(len > 0);
+    if i >= 0 && i < len {
+        return Some(i);
+    }
+    let rem = |i: i32, n: i32| ((i % n) + n) % n;
+    match border {
+        BorderType::Replicate => Some(clip(i, 0, len - 1)),
+        BorderType::Constant(_) => None,
+        BorderType::Reflect => {
+            let m = rem(i, 2 * len);
+            Some(if m < len { m } else { 2 * len - 1 - m })
+        },
+        BorderType::Reflect101 => {
+            if len == 1 {
+                return Some(0);
+            }
+            let p = 2 * (len - 1);
+            let m = rem(i, p);
+            Some(if m < len { m } else { p - m })
+        },
+        BorderType::Wrap => Some(rem(i, len))
+    }
+}
+
+/// The value of a `Constant` border saturated to `S`, zero for the other
+/// borders.
+#[inline]
+pub fn border_value<S: SaturateCast>(border: BorderType) -> S {
+    match border {
+        BorderType::Constant(v) => S::saturate_cast(v),
+        _ => S::saturate_cast(0.0)
+    }
+}
+
+/// `border_index` of the `len + k - 1` positions covered by a window of
+/// `k` taps with its tap `anchor` over each of `0..len`.
+pub fn border_map(len: u32, k: u32, anchor: i32, border: BorderType) -> Vec<Option<usize>> {
+    (0..(len + k - 1) as i32)
+        .map(|i| border_index(i - anchor, len as i32, border).map(|v| v as usize))
+        .collect()
+}
+
+/// Every border type, with `cval` as the constant.
+#[cfg(test)]
+pub fn all_borders(cval: f32) -> [BorderType; 5] {
+    [BorderType::Replicate, BorderType::Constant(cval), BorderType::Reflect,
+     BorderType::Reflect101, BorderType::Wrap]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn extend(border: BorderType) -> Vec<Option<i32>> {
+        (-3..7).map(|i| border_index(i, 4, border)).collect()
+    }
+
+    #[test]
+    fn test_border_index() {
+        let s = |v: &[i32]| v.iter().map(|&i| Some(i)).collect::<Vec<_>>();
+        assert_eq!(extend(BorderType::Replicate), s(&[0, 0, 0, 0, 1, 2, 3, 3, 3, 3]));
+        assert_eq!(extend(BorderType::Reflect), s(&[2, 1, 0, 0, 1, 2, 3, 3, 2, 1]));
+        assert_eq!(extend(BorderType::Reflect101), s(&[3, 2, 1, 0, 1, 2, 3, 2, 1, 0]));
+        assert_eq!(extend(BorderType::Wrap), s(&[1, 2, 3, 0, 1, 2, 3, 0, 1, 2]));
+        let c = extend(BorderType::Constant(0.0));
+        assert_eq!(&c[3..7], &s(&[0, 1, 2, 3])[..]);
+        assert!(c[..3].iter().chain(c[7..].iter()).all(|i| i.is_none()));
+
+        // kernels larger than the image
+        assert_eq!(border_index(-9, 4, BorderType::Reflect), Some(0));
+        assert_eq!(border_index(-9, 4, BorderType::Reflect101), Some(3));
+        assert_eq!(border_index(5, 1, BorderType::Reflect101), Some(0));
+    }
+
+    #[test]
+    fn test_border_map() {
+        let m = border_map(4, 3, 1, BorderType::Constant(7.0));
+        assert_eq!(m, vec![None, Some(0), Some(1), Some(2), Some(3), None]);
+        assert_eq!(border_map(3, 2, 0, BorderType::Wrap), vec![Some(0), Some(1), Some(2), Some(0)]);
+        assert_eq!(border_value::<u8>(BorderType::Constant(300.0)), 255);
+        assert_eq!(border_value::<f32>(BorderType::Constant(-1.5)), -1.5);
+        assert_eq!(border_value::<f64>(BorderType::Reflect), 0.0);
+    }
+}
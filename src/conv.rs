@@ -1,58 +1,127 @@
 use image::*;
-use num::NumCast;
-use math::utils::*;
 use num::traits::ToPrimitive;
-use std::ptr;
-use traits::Primitive;
+use traits::{Primitive, SaturateCast};
+use par::for_each_row_mut_with;
+use border::{BorderType, border_index, border_map, border_value};
+use geo::Pointi;
+use math::fft::{fft2d, fft_size};
+use num::complex::Complex64;
 
+/// Correlates `row` with `kernel`, replicating the edge values.
 #[inline]
 pub fn conv1d<T: Primitive>(row: &[T], out: &mut [f32], kernel: &[f32]) {
+    conv1d_with_border(row, out, kernel, BorderType::Replicate)
+}
+
+/// Like `conv1d`, extending `row` as described by `border`.
+#[inline]
+pub fn conv1d_with_border<T: Primitive>(row: &[T], out: &mut [f32], kernel: &[f32],
+                                        border: BorderType) {
     assert!(row.len() <= out.len());
     let hx = kernel.len() as i32 / 2;
     let w = row.len() as i32;
     for i in 0..w {
         let mut s = 0f32;
         for j in 0..kernel.len() as i32 {
-            let xi = clip((i - hx) + j, 0, w - 1) as usize;
-            s += row[xi].to_f32().unwrap() * kernel[j as usize];
+            let v = match border_index((i - hx) + j, w, border) {
+                Some(xi) => row[xi as usize].to_f32().unwrap(),
+                None => border_value(border)
+            };
+            s += v * kernel[j as usize];
         }
         out[i as usize] = s;
     }
 }
 
-pub fn conv2d_sep<T: Pixel>(src: &Image<T>, kernelx: &[f32], kernely: &[f32]) -> Image<T> 
+/// Correlates `src` with `kernelx` along the rows and `kernely` along the
+/// columns, replicating the edge pixels. Results are truncated towards
+/// zero and saturate to the pixel type, `conv2d_sep_to` rounds them.
+pub fn conv2d_sep<T, I>(src: &I, kernelx: &[f32], kernely: &[f32]) -> Image<T>
+    where T: Pixel,
+          I: GenericImage<Pixel=T>
+{
+    conv2d_sep_with_border(src, kernelx, kernely, BorderType::Replicate)
+}
+
+/// Like `conv2d_sep`, extending `src` as described by `border`.
+pub fn conv2d_sep_with_border<T, I>(src: &I, kernelx: &[f32], kernely: &[f32],
+                                    border: BorderType) -> Image<T>
+    where T: Pixel,
+          I: GenericImage<Pixel=T>
+{
+    conv2d_sep_cast(src, kernelx, kernely, 1.0, 0.0, border, SaturateCast::saturate_trunc)
+}
+
+/// Like `conv2d_sep`, writing `scale * v + delta` rounded and saturated
+/// to the output type, e.g. `Gray<i16>` derivatives of a `Gray<u8>` image.
+pub fn conv2d_sep_to<T, U, I>(src: &I, kernelx: &[f32], kernely: &[f32],
+                              scale: f32, delta: f32, border: BorderType) -> Image<U>
+    where T: Pixel,
+          U: Pixel,
+          I: GenericImage<Pixel=T>
 {
-    let mut dst: Image<T> = Image::new(src.width(), src.height());
+    conv2d_sep_cast(src, kernelx, kernely, scale, delta, border, SaturateCast::saturate_cast)
+}
+
+fn conv2d_sep_cast<T, U, I>(src: &I, kernelx: &[f32], kernely: &[f32], scale: f32, delta: f32,
+                            border: BorderType, cast: fn(f32) -> U::Subpixel) -> Image<U>
+    where T: Pixel,
+          U: Pixel,
+          I: GenericImage<Pixel=T>
+{
+    assert_eq!(T::channels(), U::channels());
+    let mut dst: Image<U> = Image::new(src.width(), src.height());
     let height = src.height();
     let width = src.width();
     let channels = T::channels() as usize;
-    let mut row_off = vec![0u32; kernely.len()];
 
     let hkxw = kernelx.len() / 2;
     let tmpsz = channels * (width as usize + kernelx.len() + 1);
-    let mut tmp = vec![0f32; tmpsz];
-    for y in 0..height {
-        let pdst = dst.row_mut(y);
+    let cval: f32 = border_value(border);
+    // a constant border filtered by kernely
+    let cvaly = cval * kernely.iter().fold(0f32, |s, k| s + k);
+    for_each_row_mut_with(&mut dst,
+        || (vec![None; kernely.len()], vec![0f32; tmpsz]),
+        |&mut (ref mut row_off, ref mut tmp), y, pdst| {
         for i in 0..kernely.len() {
             let yy = y as i32 - kernely.len() as i32 / 2 + i as i32;
-            row_off[i] = clip(yy, 0, height as i32 - 1) as u32;
+            row_off[i] = border_index(yy, height as i32, border);
+        }
+        for v in tmp.iter_mut() {
+            *v = 0.0;
         }
-        unsafe { ptr::write_bytes(tmp.as_mut_ptr(), 0, tmpsz); }
         for x in 0..width {
             let tx = (x as usize + hkxw) * channels;
             for i in 0..kernely.len() {
-                let r = src.row(row_off[i]);
-                for c in 0..channels {
-                    tmp[tx + c] += r[x as usize].raw()[c].to_f32().unwrap() * kernely[i];
+                match row_off[i] {
+                    Some(yy) => {
+                        let r = src.row(yy as u32);
+                        for c in 0..channels {
+                            tmp[tx + c] += r[x as usize].raw()[c].to_f32().unwrap() * kernely[i];
+                        }
+                    },
+                    None => {
+                        for c in 0..channels {
+                            tmp[tx + c] += cval * kernely[i];
+                        }
+                    }
                 }
             }
         }
-        for x in 0..hkxw {
+        for x in (0..hkxw).chain(width as usize + hkxw..width as usize + 2 * hkxw) {
             let tx = x * channels;
-            let tx1 = (x + width as usize + hkxw) * channels;
-            for c in 0..channels {
-                tmp[tx + c] = tmp[hkxw * channels + c];
-                tmp[tx1 + c] = tmp[(hkxw + width as usize - 1) * channels + c];
+            match border_index(x as i32 - hkxw as i32, width as i32, border) {
+                Some(xx) => {
+                    let ts = (xx as usize + hkxw) * channels;
+                    for c in 0..channels {
+                        tmp[tx + c] = tmp[ts + c];
+                    }
+                },
+                None => {
+                    for c in 0..channels {
+                        tmp[tx + c] = cvaly;
+                    }
+                }
             }
         }
         for x in 0..width {
@@ -65,10 +134,10 @@ pub fn conv2d_sep<T: Pixel>(src: &Image<T>, kernelx: &[f32], kernely: &[f32]) ->
                 }
             }
             for c in 0..channels {
-                pdst[x as usize].raw_mut()[c] = NumCast::from(px[c]).unwrap();
+                pdst[x as usize].raw_mut()[c] = cast(px[c] * scale + delta);
             }
         }
-    }
+    });
     dst
 }
 
@@ -94,10 +163,219 @@ fn gaussian_kernel(w: usize, sigma: f32) -> Vec<f32> {
     k
 }
 
-pub fn gaussian_blur<T: Pixel>(src: &Image<T>, kernel_width :usize, sigma: f32) -> Image<T> {
+pub fn gaussian_blur<T, I>(src: &I, kernel_width :usize, sigma: f32) -> Image<T>
+    where T: Pixel,
+          I: GenericImage<Pixel=T>
+{
+    gaussian_blur_with_border(src, kernel_width, sigma, BorderType::Replicate)
+}
+
+/// Like `gaussian_blur`, extending `src` as described by `border`.
+pub fn gaussian_blur_with_border<T, I>(src: &I, kernel_width :usize, sigma: f32,
+                                       border: BorderType) -> Image<T>
+    where T: Pixel,
+          I: GenericImage<Pixel=T>
+{
     assert!(kernel_width >= 1);
     let k = gaussian_kernel(kernel_width, sigma);
-    conv2d_sep(src, &k, &k)
+    conv2d_sep_with_border(src, &k, &k, border)
+}
+
+// kernels with at least this many taps are applied in the frequency domain
+const FFT_MIN_KERNEL_AREA: u32 = 11 * 11;
+
+/// Correlates `src` with an arbitrary `kernel`, which like in `conv2d_sep`
+/// is not flipped. `anchor` is the kernel tap aligned with the output
+/// pixel, negative coordinates select the kernel center. Separable
+/// kernels are delegated to `conv2d_sep_to`, large ones are applied with
+/// an FFT. Results are rounded and saturate to the pixel type.
+pub fn filter2d<T, I>(src: &I, kernel: &Image<Gray<f32>>, anchor: Pointi,
+                      border: BorderType) -> Image<T>
+    where T: Pixel,
+          I: GenericImage<Pixel=T>
+{
+    let (kw, kh) = kernel.size();
+    assert!(kw >= 1 && kh >= 1);
+    let ax = if anchor.x < 0 { kw as i32 / 2 } else { anchor.x };
+    let ay = if anchor.y < 0 { kh as i32 / 2 } else { anchor.y };
+    assert!(ax < kw as i32 && ay < kh as i32, "anchor {} outside of the kernel", anchor);
+    let anchor = Pointi::new(ax, ay);
+
+    if let Some((kx, ky)) = separate_kernel(kernel) {
+        let kx = center_kernel(&kx, ax as usize);
+        let ky = center_kernel(&ky, ay as usize);
+        return conv2d_sep_to(src, &kx, &ky, 1.0, 0.0, border);
+    }
+    if kw * kh >= FFT_MIN_KERNEL_AREA {
+        filter2d_fft(src, kernel, anchor, border)
+    } else {
+        filter2d_direct(src, kernel, anchor, border)
+    }
+}
+
+// Splits a rank-1 kernel into its row and column factors: the row and
+// the column through the entry of largest magnitude `p`, the column
+// divided by `p`. The kernel is rank 1 iff every 2x2 minor through that
+// entry is zero, up to the rounding of kernels built in `f32`.
+fn separate_kernel(kernel: &Image<Gray<f32>>) -> Option<(Vec<f32>, Vec<f32>)> {
+    let (kw, kh) = (kernel.width() as usize, kernel.height() as usize);
+    let k = |x: usize, y: usize| kernel.row(y as u32)[x][0] as f64;
+    let (mut px, mut py) = (0, 0);
+    for y in 0..kh {
+        for x in 0..kw {
+            if k(x, y).abs() > k(px, py).abs() {
+                px = x;
+                py = y;
+            }
+        }
+    }
+    let p = k(px, py);
+    if p == 0.0 {
+        return Some((vec![0f32; kw], vec![0f32; kh]));
+    }
+
+    // the products of two `f32` are exact in `f64`
+    let eps = 1e-6 * p * p;
+    for y in 0..kh {
+        for x in 0..kw {
+            if (k(x, y) * p - k(px, y) * k(x, py)).abs() > eps {
+                return None;
+            }
+        }
+    }
+    let kx = (0..kw).map(|x| k(x, py) as f32).collect();
+    let ky = (0..kh).map(|y| (k(px, y) / p) as f32).collect();
+    Some((kx, ky))
+}
+
+// pads a 1D kernel with zeros so that tap `anchor` becomes its center
+fn center_kernel(k: &[f32], anchor: usize) -> Vec<f32> {
+    let r = anchor.max(k.len() - 1 - anchor);
+    let mut out = vec![0f32; 2 * r + 1];
+    out[r - anchor..r - anchor + k.len()].copy_from_slice(k);
+    out
+}
+
+fn filter2d_direct<T, I>(src: &I, kernel: &Image<Gray<f32>>, anchor: Pointi,
+                         border: BorderType) -> Image<T>
+    where T: Pixel,
+          I: GenericImage<Pixel=T>
+{
+    let (width, height) = src.size();
+    let (kw, kh) = (kernel.width() as usize, kernel.height() as usize);
+    let channels = T::channels() as usize;
+    let cval: f32 = border_value(border);
+
+    let mut taps = Vec::new();
+    for j in 0..kh {
+        for i in 0..kw {
+            let k = kernel.row(j as u32)[i][0];
+            if k != 0.0 {
+                taps.push((i, j, k));
+            }
+        }
+    }
+    // source column of x + i for every output x and tap column i
+    let cols = border_map(width, kw as u32, anchor.x, border);
+
+    let mut dst: Image<T> = Image::new(width, height);
+    for_each_row_mut_with(&mut dst, || vec![None; kh], |rows, y, pdst| {
+        for j in 0..kh {
+            rows[j] = border_index(y as i32 + j as i32 - anchor.y, height as i32, border);
+        }
+        for x in 0..width as usize {
+            let mut px = [0f32; MAX_CHANNEL_COUNT];
+            for &(i, j, k) in taps.iter() {
+                match (rows[j], cols[x + i]) {
+                    (Some(yy), Some(xx)) => {
+                        let p = src.row(yy as u32)[xx];
+                        for c in 0..channels {
+                            px[c] += p.raw()[c].to_f32().unwrap() * k;
+                        }
+                    },
+                    _ => {
+                        for c in 0..channels {
+                            px[c] += cval * k;
+                        }
+                    }
+                }
+            }
+            for c in 0..channels {
+                pdst[x].raw_mut()[c] = SaturateCast::saturate_cast(px[c]);
+            }
+        }
+    });
+    dst
+}
+
+fn filter2d_fft<T, I>(src: &I, kernel: &Image<Gray<f32>>, anchor: Pointi,
+                      border: BorderType) -> Image<T>
+    where T: Pixel,
+          I: GenericImage<Pixel=T>
+{
+    let (width, height) = (src.width() as usize, src.height() as usize);
+    let (kw, kh) = (kernel.width() as usize, kernel.height() as usize);
+    let channels = T::channels() as usize;
+    let cval: f64 = border_value(border);
+    let zero = Complex64::new(0.0, 0.0);
+
+    // the source extended by the border, valid correlation with the kernel
+    // yields the output
+    let (ew, eh) = (width + kw - 1, height + kh - 1);
+    let (fw, fh) = (fft_size(ew), fft_size(eh));
+    let cols = border_map(width as u32, kw as u32, anchor.x, border);
+    let rows = border_map(height as u32, kh as u32, anchor.y, border);
+
+    // correlating is convolving with the flipped kernel
+    let mut kf = vec![zero; fw * fh];
+    for j in 0..kh {
+        let pk = kernel.row(j as u32);
+        for i in 0..kw {
+            kf[(kh - 1 - j) * fw + kw - 1 - i] = Complex64::new(pk[i][0] as f64, 0.0);
+        }
+    }
+    fft2d(&mut kf, fw, fh, false);
+
+    let scale = 1.0 / (fw * fh) as f64;
+    let mut buf = vec![zero; fw * fh];
+    let mut dst: Image<T> = Image::new(width as u32, height as u32);
+    for c in 0..channels {
+        for v in buf.iter_mut() {
+            *v = zero;
+        }
+        for ey in 0..eh {
+            let pbuf = &mut buf[ey * fw..ey * fw + ew];
+            match rows[ey] {
+                Some(yy) => {
+                    let psrc = src.row(yy as u32);
+                    for ex in 0..ew {
+                        pbuf[ex].re = match cols[ex] {
+                            Some(xx) => psrc[xx].raw()[c].to_f64().unwrap(),
+                            None => cval
+                        };
+                    }
+                },
+                None => {
+                    for v in pbuf.iter_mut() {
+                        v.re = cval;
+                    }
+                }
+            }
+        }
+        fft2d(&mut buf, fw, fh, false);
+        for (a, b) in buf.iter_mut().zip(kf.iter()) {
+            *a = *a * *b;
+        }
+        fft2d(&mut buf, fw, fh, true);
+        for y in 0..height {
+            let pbuf = &buf[(y + kh - 1) * fw + kw - 1..];
+            let pdst = dst.row_mut(y as u32);
+            for x in 0..width {
+                pdst[x].raw_mut()[c] = SaturateCast::saturate_cast((pbuf[x].re * scale) as f32);
+            }
+        }
+    }
+    dst
 }
 
 #[cfg(test)]
@@ -107,6 +385,8 @@ mod test {
     use super::*;
     use imageio::ImageIO;
     use imageio::FreeImageIO;
+    use geo::{Pointi, Recti};
+    use border::all_borders;
 
     #[test]
     fn test_conv1d() {
@@ -128,5 +408,249 @@ mod test {
         let target = Path::new("/tmp/test-conv-out1.jpg");
         FreeImageIO::save(&target, &out).unwrap();
     }
+
+    #[test]
+    fn test_conv2d_sep_view() {
+        let mut img = ImageGray::new(8, 8);
+        img.fill(&Gray([0]));
+        img.view_mut(&Recti::new(2, 2, 4, 4)).fill(&Gray([90]));
+
+        let roi = Recti::new(2, 2, 4, 4);
+        let out = conv2d_sep_with_border(&img.view(&roi), &[1.0/3.0; 3], &[1.0],
+                             BorderType::Replicate);
+        assert_eq!(out.size(), (4, 4));
+        for (_, _, p) in out.iter() {
+            assert_eq!(*p, Gray([90]));
+        }
+    }
+
+    // reads pixel x - 2 for `kx`, y - 2 for `ky`
+    const SHIFT: [f32; 5] = [1.0, 0.0, 0.0, 0.0, 0.0];
+    const SHIFT_BACK: [f32; 5] = [0.0, 0.0, 0.0, 0.0, 1.0];
+
+    fn row(img: &Image<Gray<f32>>) -> Vec<f32> {
+        img.row(0).iter().map(|p| p[0]).collect()
+    }
+
+    fn col(img: &Image<Gray<f32>>) -> Vec<f32> {
+        (0..img.height()).map(|y| img.row(y)[0][0]).collect()
+    }
+
+    #[test]
+    fn test_conv2d_sep_border() {
+        let h = Image::from_fn(4, 1, |x, _| Gray([x as f32 + 1.0]));
+        let v = Image::from_fn(1, 4, |_, y| Gray([y as f32 + 1.0]));
+        let cases = [(BorderType::Replicate, [1.0, 1.0], [4.0, 4.0]),
+                     (BorderType::Constant(9.0), [9.0, 9.0], [9.0, 9.0]),
+                     (BorderType::Reflect, [2.0, 1.0], [4.0, 3.0]),
+                     (BorderType::Reflect101, [3.0, 2.0], [3.0, 2.0]),
+                     (BorderType::Wrap, [3.0, 4.0], [1.0, 2.0])];
+        for &(border, left, right) in cases.iter() {
+            let l = row(&conv2d_sep_with_border(&h, &SHIFT, &[1.0], border));
+            assert_eq!(l, vec![left[0], left[1], 1.0, 2.0], "{:?}", border);
+            let r = row(&conv2d_sep_with_border(&h, &SHIFT_BACK, &[1.0], border));
+            assert_eq!(r, vec![3.0, 4.0, right[0], right[1]], "{:?}", border);
+
+            let t = col(&conv2d_sep_with_border(&v, &[1.0], &SHIFT, border));
+            assert_eq!(t, vec![left[0], left[1], 1.0, 2.0], "{:?}", border);
+            let b = col(&conv2d_sep_with_border(&v, &[1.0], &SHIFT_BACK, border));
+            assert_eq!(b, vec![3.0, 4.0, right[0], right[1]], "{:?}", border);
+
+            let mut out = [0f32; 4];
+            conv1d_with_border(&[1.0f32, 2.0, 3.0, 4.0], &mut out, &SHIFT, border);
+            assert_eq!(out, [left[0], left[1], 1.0, 2.0], "{:?}", border);
+        }
+
+        // corners of a constant border are constant too
+        let img = ImageGrayf::from_pixel(3, 3, Gray([1.0]));
+        let out = conv2d_sep_with_border(&img, &SHIFT, &SHIFT, BorderType::Constant(5.0));
+        assert_eq!(out[(0, 0)], Gray([5.0]));
+        assert_eq!(out[(2, 1)], Gray([5.0]));
+        assert_eq!(out[(2, 2)], Gray([1.0]));
+    }
+
+    #[test]
+    fn test_gaussian_blur_border() {
+        let img = ImageGrayf::from_pixel(6, 6, Gray([1.0]));
+        for &border in [BorderType::Replicate, BorderType::Reflect,
+                        BorderType::Reflect101, BorderType::Wrap].iter() {
+            let out = gaussian_blur_with_border(&img, 5, 0.0, border);
+            assert!(out.iter().all(|(_, _, p)| (p[0] - 1.0).abs() < 1e-5));
+        }
+        let out = gaussian_blur_with_border(&img, 5, 0.0, BorderType::Constant(0.0));
+        assert!(out[(0, 0)][0] < out[(1, 1)][0]);
+        assert!((out[(3, 3)][0] - 1.0).abs() < 1e-5);
+    }
+
+    fn kernel<F: Fn(u32, u32) -> f32>(w: u32, h: u32, f: F) -> Image<Gray<f32>> {
+        Image::from_fn(w, h, |x, y| Gray([f(x, y)]))
+    }
+
+    // straightforward correlation to compare against
+    fn reference(src: &Image<Gray<f32>>, k: &Image<Gray<f32>>, anchor: Pointi,
+                 border: BorderType) -> Vec<f32> {
+        let (w, h) = (src.width() as i32, src.height() as i32);
+        let mut out = Vec::new();
+        for y in 0..h {
+            for x in 0..w {
+                let mut s = 0f32;
+                for j in 0..k.height() as i32 {
+                    for i in 0..k.width() as i32 {
+                        let v = match (border_index(x + i - anchor.x, w, border),
+                                       border_index(y + j - anchor.y, h, border)) {
+                            (Some(xx), Some(yy)) => src[(xx as u32, yy as u32)][0],
+                            _ => border_value(border)
+                        };
+                        s += v * k[(i as u32, j as u32)][0];
+                    }
+                }
+                out.push(s);
+            }
+        }
+        out
+    }
+
+    fn close(img: &Image<Gray<f32>>, expected: &[f32], eps: f32) -> bool {
+        img.iter().zip(expected.iter()).all(|((_, _, p), e)| (p[0] - e).abs() <= eps)
+    }
+
+    #[test]
+    fn test_separate_kernel() {
+        let k = kernel(3, 2, |x, y| [1.0, 2.0, 1.0][x as usize] * [-1.0, 1.0][y as usize]);
+        let (kx, ky) = separate_kernel(&k).unwrap();
+        for y in 0..2 {
+            for x in 0..3 {
+                assert!((kx[x] * ky[y] - k[(x as u32, y as u32)][0]).abs() < 1e-5);
+            }
+        }
+        let emboss = kernel(3, 3, |x, y| x as f32 - y as f32);
+        assert!(separate_kernel(&emboss).is_none());
+        let g = gaussian_kernel(7, 1.3);
+        let k = kernel(7, 7, |x, y| g[x as usize] * g[y as usize]);
+        let (kx, ky) = separate_kernel(&k).unwrap();
+        for i in 0..7 {
+            assert!((kx[i] * ky[i] - g[i] * g[i]).abs() < 1e-6);
+        }
+        let near = kernel(3, 3, |x, y| {
+            ((x + 1) * (y + 1)) as f32 + if x * y == 4 { 1e-3 } else { 0.0 }
+        });
+        assert!(separate_kernel(&near).is_none());
+        assert_eq!(center_kernel(&[1.0, 2.0], 0), vec![0.0, 1.0, 2.0]);
+        assert_eq!(center_kernel(&[1.0, 2.0, 3.0], 2), vec![1.0, 2.0, 3.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn test_filter2d() {
+        let src = Image::from_fn(9, 7, |x, y| Gray([((x * 7 + y * 13) % 11) as f32]));
+        let emboss = kernel(3, 3, |x, y| x as f32 - y as f32 + if x == 1 { 0.5 } else { 0.0 });
+        let sep = kernel(3, 5, |x, y| (x + 1) as f32 * (y as f32 - 1.5));
+        for &border in all_borders(3.0).iter() {
+            for &anchor in [Pointi::new(-1, -1), Pointi::new(0, 2), Pointi::new(2, 0)].iter() {
+                let a = Pointi::new(if anchor.x < 0 { 1 } else { anchor.x },
+                                    if anchor.y < 0 { 1 } else { anchor.y });
+                let out: Image<Gray<f32>> = filter2d(&src, &emboss, anchor, border);
+                assert!(close(&out, &reference(&src, &emboss, a, border), 1e-4),
+                        "{:?} {}", border, anchor);
+
+                let a = Pointi::new(a.x, if anchor.y < 0 { 2 } else { a.y });
+                let out: Image<Gray<f32>> = filter2d(&src, &sep, anchor, border);
+                assert!(close(&out, &reference(&src, &sep, a, border), 1e-3),
+                        "{:?} {}", border, anchor);
+            }
+        }
+
+        // a single tap in the corner shifts the image
+        let shift = kernel(3, 3, |x, y| if x == 0 && y == 0 { 1.0 } else { 0.0 });
+        let out: Image<Gray<f32>> = filter2d(&src, &shift, Pointi::new(-1, -1),
+                                             BorderType::Replicate);
+        assert_eq!(out[(4, 3)], src[(3, 2)]);
+        let out: Image<Gray<f32>> = filter2d(&src, &shift, Pointi::new(0, 0),
+                                             BorderType::Replicate);
+        assert_eq!(out[(4, 3)], src[(4, 3)]);
+    }
+
+    #[test]
+    fn test_filter2d_fft() {
+        let src = Image::from_fn(23, 17, |x, y| Gray([((x * 5 + y * y) % 13) as f32]));
+        let k = kernel(13, 11, |x, y| ((x * 3 + y * 7) % 5) as f32 - 2.0 + (x * y) as f32 * 0.01);
+        assert!(k.width() * k.height() >= FFT_MIN_KERNEL_AREA);
+        assert!(separate_kernel(&k).is_none());
+        for &border in all_borders(3.0).iter() {
+            let anchor = Pointi::new(3, 8);
+            let fft: Image<Gray<f32>> = filter2d_fft(&src, &k, anchor, border);
+            let direct: Image<Gray<f32>> = filter2d_direct(&src, &k, anchor, border);
+            let expected = reference(&src, &k, anchor, border);
+            assert!(close(&fft, &expected, 1e-2), "{:?}", border);
+            assert!(close(&direct, &expected, 1e-2), "{:?}", border);
+        }
+
+        let bgr = Image::from_fn(15, 12, |x, y| Bgr([x as f32, y as f32, (x * y) as f32]));
+        let fft: Image<Bgr<f32>> = filter2d_fft(&bgr, &k, Pointi::new(6, 5), BorderType::Wrap);
+        let direct: Image<Bgr<f32>> = filter2d_direct(&bgr, &k, Pointi::new(6, 5),
+                                                     BorderType::Wrap);
+        for ((_, _, a), (_, _, b)) in fft.iter().zip(direct.iter()) {
+            assert!((0..3).all(|c| (a[c] - b[c]).abs() < 1e-2));
+        }
+    }
+
+    #[test]
+    fn test_filter2d_saturate() {
+        // emboss of a u8 image goes negative on one side of every edge
+        let src = ImageGray::from_fn(9, 7, |x, y| Gray([((x * 70 + y * 30) % 256) as u8]));
+        let srcf = Image::from_fn(9, 7, |x, y| Gray([src[(x, y)][0] as f32]));
+        let emboss = kernel(3, 3, |x, y| x as f32 - y as f32 + if x == 1 { 0.5 } else { 0.0 });
+        let expected = reference(&srcf, &emboss, Pointi::new(1, 1), BorderType::Reflect101);
+        assert!(expected.iter().any(|&v| v < 0.0) && expected.iter().any(|&v| v > 255.0));
+        let out: ImageGray = filter2d(&src, &emboss, Pointi::new(-1, -1), BorderType::Reflect101);
+        for ((_, _, p), e) in out.iter().zip(expected.iter()) {
+            assert_eq!(p[0], e.round().max(0.0).min(255.0) as u8);
+        }
+
+        let big = kernel(13, 11, |x, y| ((x * 3 + y * 7) % 5) as f32 - 2.0);
+        let fft: ImageGray = filter2d_fft(&src, &big, Pointi::new(6, 5), BorderType::Wrap);
+        let direct: ImageGray = filter2d_direct(&src, &big, Pointi::new(6, 5), BorderType::Wrap);
+        assert!(fft.iter().zip(direct.iter()).all(|((_, _, a), (_, _, b))| {
+            (a[0] as i32 - b[0] as i32).abs() <= 1
+        }));
+    }
+
+    #[test]
+    fn test_conv2d_sep_to() {
+        // a step from 10 to 200
+        let src = ImageGray::from_fn(6, 3, |x, _| Gray([if x < 3 { 10 } else { 200 }]));
+        let deriv = [-1.0, 0.0, 1.0];
+
+        let out: Image<Gray<i16>> = conv2d_sep_to(&src, &deriv, &[1.0], 1.0, 0.0,
+                                                  BorderType::Replicate);
+        assert_eq!(out.row(1), &[Gray([0]), Gray([0]), Gray([190]),
+                                 Gray([190]), Gray([0]), Gray([0])]);
+        let out: Image<Gray<i16>> = conv2d_sep_to(&src, &[1.0, 0.0, -1.0], &[1.0], 1.0, 0.0,
+                                                  BorderType::Replicate);
+        assert_eq!(out[(2, 0)], Gray([-190]));
+        let out: Image<Gray<f32>> = conv2d_sep_to(&src, &deriv, &[1.0], 0.5, -1.0,
+                                                  BorderType::Replicate);
+        assert_eq!(out[(3, 2)], Gray([94.0]));
+        assert_eq!(out[(0, 0)], Gray([-1.0]));
+
+        // negative and overflowing results saturate, conv2d_sep truncates
+        let out: ImageGray = conv2d_sep(&src, &[1.0, 0.0, -1.0], &[1.0]);
+        assert_eq!(out[(2, 0)], Gray([0]));
+        let out: ImageGray = conv2d_sep(&src, &[0.499, 0.0, 0.0], &[1.0]);
+        assert_eq!(out[(4, 0)], Gray([99]));
+        let out: ImageGray = conv2d_sep_to(&src, &[0.499, 0.0, 0.0], &[1.0], 1.0, 0.0,
+                                           BorderType::Replicate);
+        assert_eq!(out[(4, 0)], Gray([100]));
+        let out: ImageGray = conv2d_sep_to(&src, &deriv, &[1.0], 2.0, 0.0,
+                                           BorderType::Replicate);
+        assert_eq!(out[(2, 0)], Gray([255]));
+        let out: ImageGray = conv2d_sep_to(&src, &deriv, &[1.0], 1.0, 128.0,
+                                           BorderType::Replicate);
+        assert_eq!(out.row(0)[1..4], [Gray([128]), Gray([255]), Gray([255])]);
+
+        let bgr = ImageBgr::from_pixel(3, 3, Bgr([10, 20, 30]));
+        let out: Image<Bgr<f32>> = conv2d_sep_to(&bgr, &[1.0], &[0.5, 0.5], 1.0 / 255.0, 0.0,
+                                                 BorderType::Reflect);
+        assert!((out[(1, 1)][2] - 30.0 / 255.0).abs() < 1e-6);
+    }
 }
 
@@ -1,13 +1,25 @@
 use std::ops::{Index, IndexMut};
 use num::traits::ToPrimitive;
 use image::*;
-use traits::Primitive;
+use traits::{Primitive, SaturateCast};
+use par::for_each_row_mut;
+use simd;
+use math::utils::clipped_round;
 
 pub trait ColorMapper {
     type SrcType: Pixel;
     type DstType: Pixel;
 
     fn to(src: &Self::SrcType) -> Self::DstType;
+
+    /// Converts a row of pixels, mappers override this with vectorized
+    /// versions where available.
+    #[inline]
+    fn map_row(src: &[Self::SrcType], dst: &mut [Self::DstType]) {
+        for (d, s) in dst.iter_mut().zip(src.iter()) {
+            *d = Self::to(s);
+        }
+    }
 }
 
 pub struct MapBgraGray;
@@ -17,10 +29,11 @@ impl ColorMapper for MapBgraGray {
 
     #[inline(always)]
     fn to(src: &Self::SrcType) -> Self::DstType {
-        let d = ((src[0] as u32 * 28
-                + src[1] as u32 * 151
-                + src[2] as u32 * 77) >> 8) as u8;
-        Gray([d])
+        Gray([gray_u8(src[0], src[1], src[2])])
+    }
+
+    fn map_row(src: &[Self::SrcType], dst: &mut [Self::DstType]) {
+        simd::bgra_to_gray(src, dst)
     }
 }
 
@@ -34,6 +47,10 @@ impl ColorMapper for MapGrayBgra {
         let v = src[0];
         Bgra([v, v, v, 255])
     }
+
+    fn map_row(src: &[Self::SrcType], dst: &mut [Self::DstType]) {
+        simd::gray_to_bgra(src, dst)
+    }
 }
 
 pub struct MapGrayBgr;
@@ -55,10 +72,7 @@ impl ColorMapper for MapBgrGray {
 
     #[inline(always)]
     fn to(src: &Self::SrcType) -> Self::DstType {
-        let d = ((src[0] as u32 * 28
-                + src[1] as u32 * 151
-                + src[2] as u32 * 77) >> 8) as u8;
-        Gray([d])
+        Gray([gray_u8(src[0], src[1], src[2])])
     }
 }
 
@@ -73,33 +87,622 @@ impl ColorMapper for MapGrayGrayf {
     }
 }
 
+// Color spaces
+//
+// The conversions follow OpenCV. `f32` images hold BGR in [0, 1] and
+// produce H in degrees [0, 360), S, V, L in [0, 1], L* in [0, 100].
+// `u8` images store H / 2, scale [0, 1] ranges to [0, 255], offset a* and
+// b* by 128, and map L*u*v* to [0, 255]. XYZ is computed on the BGR values
+// directly while Lab and Luv linearize sRGB first.
+
+const LAB_XN: f32 = 0.950456;
+const LAB_ZN: f32 = 1.088754;
+// u' and v' of the white point
+const LUV_UN: f32 = 4.0 * LAB_XN / (LAB_XN + 15.0 + 3.0 * LAB_ZN);
+const LUV_VN: f32 = 9.0 / (LAB_XN + 15.0 + 3.0 * LAB_ZN);
+
+// luma of BGR with the BT.601 weights in 8 bits fixed point, rounded;
+// the definition every BGR to gray conversion follows
+#[inline(always)]
+fn gray_u8(b: u8, g: u8, r: u8) -> u8 {
+    ((b as u32 * 28 + g as u32 * 151 + r as u32 * 77 + 128) >> 8) as u8
+}
+
+// `gray_u8` on channels in [0, 1]. Scaled by 255 the channels of 8 bits
+// pixels are whole again, so the sum is exact and rounds like `gray_u8`
+#[inline]
+fn bgr_to_gray(p: [f32; 3]) -> f32 {
+    (28.0 * (p[0] * 255.0) + 151.0 * (p[1] * 255.0) + 77.0 * (p[2] * 255.0)) / (256.0 * 255.0)
+}
+
+#[inline]
+fn unit(p: [u8; 3]) -> [f32; 3] {
+    [p[0] as f32 / 255.0, p[1] as f32 / 255.0, p[2] as f32 / 255.0]
+}
+
+#[inline]
+fn unit_to_u8(p: [f32; 3]) -> [u8; 3] {
+    [u8::saturate_cast(p[0] * 255.0),
+     u8::saturate_cast(p[1] * 255.0),
+     u8::saturate_cast(p[2] * 255.0)]
+}
+
+fn hue(b: f32, g: f32, r: f32, vmax: f32, diff: f32) -> f32 {
+    if diff <= 0.0 {
+        return 0.0;
+    }
+    let h = if vmax == r {
+        60.0 * (g - b) / diff
+    } else if vmax == g {
+        120.0 + 60.0 * (b - r) / diff
+    } else {
+        240.0 + 60.0 * (r - g) / diff
+    };
+    if h < 0.0 { h + 360.0 } else { h }
+}
+
+fn bgr_to_hsv(p: [f32; 3]) -> [f32; 3] {
+    let (b, g, r) = (p[0], p[1], p[2]);
+    let v = b.max(g).max(r);
+    let diff = v - b.min(g).min(r);
+    let s = if v > 0.0 { diff / v } else { 0.0 };
+    [hue(b, g, r, v, diff), s, v]
+}
+
+fn hsv_to_bgr(p: [f32; 3]) -> [f32; 3] {
+    let (h, s, v) = (p[0], p[1], p[2]);
+    if s <= 0.0 {
+        return [v, v, v];
+    }
+    let h = (h / 60.0) % 6.0;
+    let h = if h < 0.0 { h + 6.0 } else { h };
+    let i = h.floor();
+    let f = h - i;
+    let p = v * (1.0 - s);
+    let q = v * (1.0 - s * f);
+    let t = v * (1.0 - s * (1.0 - f));
+    let (r, g, b) = match i as u32 {
+        0 => (v, t, p),
+        1 => (q, v, p),
+        2 => (p, v, t),
+        3 => (p, q, v),
+        4 => (t, p, v),
+        _ => (v, p, q),
+    };
+    [b, g, r]
+}
+
+fn bgr_to_hls(p: [f32; 3]) -> [f32; 3] {
+    let (b, g, r) = (p[0], p[1], p[2]);
+    let vmax = b.max(g).max(r);
+    let vmin = b.min(g).min(r);
+    let diff = vmax - vmin;
+    let l = (vmax + vmin) * 0.5;
+    let s = if diff <= 0.0 {
+        0.0
+    } else if l < 0.5 {
+        diff / (vmax + vmin)
+    } else {
+        diff / (2.0 - vmax - vmin)
+    };
+    [hue(b, g, r, vmax, diff), l, s]
+}
+
+fn hls_channel(p: f32, q: f32, h: f32) -> f32 {
+    let h = if h < 0.0 { h + 360.0 } else if h >= 360.0 { h - 360.0 } else { h };
+    if h < 60.0 {
+        p + (q - p) * h / 60.0
+    } else if h < 180.0 {
+        q
+    } else if h < 240.0 {
+        p + (q - p) * (240.0 - h) / 60.0
+    } else {
+        p
+    }
+}
+
+fn hls_to_bgr(p: [f32; 3]) -> [f32; 3] {
+    let (h, l, s) = (p[0] % 360.0, p[1], p[2]);
+    if s <= 0.0 {
+        return [l, l, l];
+    }
+    let q = if l < 0.5 { l * (1.0 + s) } else { l + s - l * s };
+    let p = 2.0 * l - q;
+    [hls_channel(p, q, h - 120.0), hls_channel(p, q, h), hls_channel(p, q, h + 120.0)]
+}
+
+fn bgr_to_ycrcb(p: [f32; 3], delta: f32) -> [f32; 3] {
+    let (b, g, r) = (p[0], p[1], p[2]);
+    let y = 0.299 * r + 0.587 * g + 0.114 * b;
+    [y, (r - y) * 0.713 + delta, (b - y) * 0.564 + delta]
+}
+
+fn ycrcb_to_bgr(p: [f32; 3], delta: f32) -> [f32; 3] {
+    let (y, cr, cb) = (p[0], p[1] - delta, p[2] - delta);
+    [y + 1.773 * cb, y - 0.714 * cr - 0.344 * cb, y + 1.403 * cr]
+}
+
+fn bgr_to_xyz(p: [f32; 3]) -> [f32; 3] {
+    let (b, g, r) = (p[0], p[1], p[2]);
+    [0.412453 * r + 0.357580 * g + 0.180423 * b,
+     0.212671 * r + 0.715160 * g + 0.072169 * b,
+     0.019334 * r + 0.119193 * g + 0.950227 * b]
+}
+
+fn xyz_to_bgr(p: [f32; 3]) -> [f32; 3] {
+    let (x, y, z) = (p[0], p[1], p[2]);
+    [0.055648 * x - 0.204043 * y + 1.057311 * z,
+     -0.969256 * x + 1.875991 * y + 0.041556 * z,
+     3.240479 * x - 1.537150 * y - 0.498535 * z]
+}
+
+#[inline]
+fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) }
+}
+
+#[inline]
+fn linear_to_srgb(c: f32) -> f32 {
+    if c <= 0.0031308 { 12.92 * c } else { 1.055 * c.powf(1.0 / 2.4) - 0.055 }
+}
+
+#[inline]
+fn lab_f(t: f32) -> f32 {
+    if t > 0.008856 { t.cbrt() } else { 7.787 * t + 16.0 / 116.0 }
+}
+
+#[inline]
+fn lab_f_inv(t: f32) -> f32 {
+    if t > 0.206893 { t * t * t } else { (t - 16.0 / 116.0) / 7.787 }
+}
+
+#[inline]
+fn lightness(y: f32) -> f32 {
+    if y > 0.008856 { 116.0 * y.cbrt() - 16.0 } else { 903.3 * y }
+}
+
+#[inline]
+fn lightness_inv(l: f32) -> f32 {
+    if l > 903.3 * 0.008856 {
+        let t = (l + 16.0) / 116.0;
+        t * t * t
+    } else {
+        l / 903.3
+    }
+}
+
+fn linear_xyz(p: [f32; 3]) -> [f32; 3] {
+    bgr_to_xyz([srgb_to_linear(p[0]), srgb_to_linear(p[1]), srgb_to_linear(p[2])])
+}
+
+fn xyz_srgb(p: [f32; 3]) -> [f32; 3] {
+    let c = xyz_to_bgr(p);
+    [linear_to_srgb(c[0]), linear_to_srgb(c[1]), linear_to_srgb(c[2])]
+}
+
+fn bgr_to_lab(p: [f32; 3]) -> [f32; 3] {
+    let xyz = linear_xyz(p);
+    let fx = lab_f(xyz[0] / LAB_XN);
+    let fy = lab_f(xyz[1]);
+    let fz = lab_f(xyz[2] / LAB_ZN);
+    [lightness(xyz[1]), 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+fn lab_to_bgr(p: [f32; 3]) -> [f32; 3] {
+    let (l, a, b) = (p[0], p[1], p[2]);
+    let y = lightness_inv(l);
+    let fy = lab_f(y);
+    let x = lab_f_inv(fy + a / 500.0) * LAB_XN;
+    let z = lab_f_inv(fy - b / 200.0) * LAB_ZN;
+    xyz_srgb([x, y, z])
+}
+
+fn bgr_to_luv(p: [f32; 3]) -> [f32; 3] {
+    let xyz = linear_xyz(p);
+    let (x, y, z) = (xyz[0], xyz[1], xyz[2]);
+    let l = lightness(y);
+    let d = x + 15.0 * y + 3.0 * z;
+    if d <= 0.0 {
+        return [l, 0.0, 0.0];
+    }
+    [l, 13.0 * l * (4.0 * x / d - LUV_UN), 13.0 * l * (9.0 * y / d - LUV_VN)]
+}
+
+fn luv_to_bgr(p: [f32; 3]) -> [f32; 3] {
+    let (l, u, v) = (p[0], p[1], p[2]);
+    if l <= 0.0 {
+        return [0.0, 0.0, 0.0];
+    }
+    let up = u / (13.0 * l) + LUV_UN;
+    let vp = v / (13.0 * l) + LUV_VN;
+    let y = lightness_inv(l);
+    let x = y * 9.0 * up / (4.0 * vp);
+    let z = y * (12.0 - 3.0 * up - 20.0 * vp) / (4.0 * vp);
+    xyz_srgb([x, y, z])
+}
+
+fn bgr_to_hsv_u8(p: [u8; 3]) -> [u8; 3] {
+    let t = bgr_to_hsv(unit(p));
+    [u8::saturate_cast(t[0] * 0.5),
+     u8::saturate_cast(t[1] * 255.0),
+     u8::saturate_cast(t[2] * 255.0)]
+}
+
+fn hsv_to_bgr_u8(p: [u8; 3]) -> [u8; 3] {
+    unit_to_u8(hsv_to_bgr([p[0] as f32 * 2.0, p[1] as f32 / 255.0, p[2] as f32 / 255.0]))
+}
+
+fn bgr_to_hls_u8(p: [u8; 3]) -> [u8; 3] {
+    let t = bgr_to_hls(unit(p));
+    [u8::saturate_cast(t[0] * 0.5),
+     u8::saturate_cast(t[1] * 255.0),
+     u8::saturate_cast(t[2] * 255.0)]
+}
+
+fn hls_to_bgr_u8(p: [u8; 3]) -> [u8; 3] {
+    unit_to_u8(hls_to_bgr([p[0] as f32 * 2.0, p[1] as f32 / 255.0, p[2] as f32 / 255.0]))
+}
+
+fn bgr_to_ycrcb_u8(p: [u8; 3]) -> [u8; 3] {
+    let t = bgr_to_ycrcb([p[0] as f32, p[1] as f32, p[2] as f32], 128.0);
+    [u8::saturate_cast(t[0]), u8::saturate_cast(t[1]), u8::saturate_cast(t[2])]
+}
+
+fn ycrcb_to_bgr_u8(p: [u8; 3]) -> [u8; 3] {
+    let t = ycrcb_to_bgr([p[0] as f32, p[1] as f32, p[2] as f32], 128.0);
+    [u8::saturate_cast(t[0]), u8::saturate_cast(t[1]), u8::saturate_cast(t[2])]
+}
+
+fn bgr_to_ycrcb_f32(p: [f32; 3]) -> [f32; 3] {
+    bgr_to_ycrcb(p, 0.5)
+}
+
+fn ycrcb_to_bgr_f32(p: [f32; 3]) -> [f32; 3] {
+    ycrcb_to_bgr(p, 0.5)
+}
+
+fn bgr_to_xyz_u8(p: [u8; 3]) -> [u8; 3] {
+    unit_to_u8(bgr_to_xyz(unit(p)))
+}
+
+fn xyz_to_bgr_u8(p: [u8; 3]) -> [u8; 3] {
+    unit_to_u8(xyz_to_bgr(unit(p)))
+}
+
+fn bgr_to_lab_u8(p: [u8; 3]) -> [u8; 3] {
+    let t = bgr_to_lab(unit(p));
+    [u8::saturate_cast(t[0] * 255.0 / 100.0),
+     u8::saturate_cast(t[1] + 128.0),
+     u8::saturate_cast(t[2] + 128.0)]
+}
+
+fn lab_to_bgr_u8(p: [u8; 3]) -> [u8; 3] {
+    unit_to_u8(lab_to_bgr([p[0] as f32 * 100.0 / 255.0,
+                           p[1] as f32 - 128.0, p[2] as f32 - 128.0]))
+}
+
+fn bgr_to_luv_u8(p: [u8; 3]) -> [u8; 3] {
+    let t = bgr_to_luv(unit(p));
+    [u8::saturate_cast(t[0] * 255.0 / 100.0),
+     u8::saturate_cast((t[1] + 134.0) * 255.0 / 354.0),
+     u8::saturate_cast((t[2] + 140.0) * 255.0 / 262.0)]
+}
+
+fn luv_to_bgr_u8(p: [u8; 3]) -> [u8; 3] {
+    unit_to_u8(luv_to_bgr([p[0] as f32 * 100.0 / 255.0,
+                           p[1] as f32 * 354.0 / 255.0 - 134.0,
+                           p[2] as f32 * 262.0 / 255.0 - 140.0]))
+}
+
+macro_rules! define_mapper(
+    ($name:ident, $src:ident, $dst:ident, $t:ty, $f:ident) => (
+pub struct $name;
+impl ColorMapper for $name {
+    type SrcType = $src<$t>;
+    type DstType = $dst<$t>;
+
+    #[inline(always)]
+    fn to(src: &Self::SrcType) -> Self::DstType {
+        $dst($f(src.data))
+    }
+}
+    );
+);
+
+define_mapper!(MapBgrHsv, Bgr, Hsv, u8, bgr_to_hsv_u8);
+define_mapper!(MapHsvBgr, Hsv, Bgr, u8, hsv_to_bgr_u8);
+define_mapper!(MapBgrHls, Bgr, Hls, u8, bgr_to_hls_u8);
+define_mapper!(MapHlsBgr, Hls, Bgr, u8, hls_to_bgr_u8);
+define_mapper!(MapBgrYCrCb, Bgr, YCrCb, u8, bgr_to_ycrcb_u8);
+define_mapper!(MapYCrCbBgr, YCrCb, Bgr, u8, ycrcb_to_bgr_u8);
+define_mapper!(MapBgrXyz, Bgr, Xyz, u8, bgr_to_xyz_u8);
+define_mapper!(MapXyzBgr, Xyz, Bgr, u8, xyz_to_bgr_u8);
+define_mapper!(MapBgrLab, Bgr, Lab, u8, bgr_to_lab_u8);
+define_mapper!(MapLabBgr, Lab, Bgr, u8, lab_to_bgr_u8);
+define_mapper!(MapBgrLuv, Bgr, Luv, u8, bgr_to_luv_u8);
+define_mapper!(MapLuvBgr, Luv, Bgr, u8, luv_to_bgr_u8);
+
+define_mapper!(MapBgrfHsvf, Bgr, Hsv, f32, bgr_to_hsv);
+define_mapper!(MapHsvfBgrf, Hsv, Bgr, f32, hsv_to_bgr);
+define_mapper!(MapBgrfHlsf, Bgr, Hls, f32, bgr_to_hls);
+define_mapper!(MapHlsfBgrf, Hls, Bgr, f32, hls_to_bgr);
+define_mapper!(MapBgrfYCrCbf, Bgr, YCrCb, f32, bgr_to_ycrcb_f32);
+define_mapper!(MapYCrCbfBgrf, YCrCb, Bgr, f32, ycrcb_to_bgr_f32);
+define_mapper!(MapBgrfXyzf, Bgr, Xyz, f32, bgr_to_xyz);
+define_mapper!(MapXyzfBgrf, Xyz, Bgr, f32, xyz_to_bgr);
+define_mapper!(MapBgrfLabf, Bgr, Lab, f32, bgr_to_lab);
+define_mapper!(MapLabfBgrf, Lab, Bgr, f32, lab_to_bgr);
+define_mapper!(MapBgrfLuvf, Bgr, Luv, f32, bgr_to_luv);
+define_mapper!(MapLuvfBgrf, Luv, Bgr, f32, luv_to_bgr);
+
+// Generic pixel conversion
+//
+// Every pixel type has a color model with a canonical `f32` layout, the
+// same one the `f32` mappers above use: BGR in [0, 1], H in degrees,
+// L* in [0, 100] and so on. Float subpixels hold canonical values as is,
+// integer subpixels use OpenCV's 8-bit layout scaled to their range, so
+// `Hsv<u8>` stores H / 2 and `Lab<u16>` stores (a* + 128) * 257.
+
+/// Subpixel types with a nominal range, [0, 255] for `u8`, [0, 65535]
+/// for `u16`, [0, MAX] for the other integers and [0, 1] for floats.
+/// Negative values of signed integers lie below the nominal range.
+pub trait Channel: Primitive {
+    /// Maps `v` from [lo, hi] to the nominal range, floats keep `v`.
+    fn encode(v: f32, lo: f32, hi: f32) -> Self;
+
+    /// Inverse of `encode`.
+    fn decode(self, lo: f32, hi: f32) -> f32;
+}
+
+impl Channel for u8 {
+    #[inline(always)]
+    fn encode(v: f32, lo: f32, hi: f32) -> u8 {
+        u8::saturate_cast((v - lo) * 255.0 / (hi - lo))
+    }
+
+    #[inline(always)]
+    fn decode(self, lo: f32, hi: f32) -> f32 {
+        lo + self as f32 * (hi - lo) / 255.0
+    }
+}
+
+impl Channel for u16 {
+    #[inline(always)]
+    fn encode(v: f32, lo: f32, hi: f32) -> u16 {
+        clipped_round((v - lo) * 65535.0 / (hi - lo), 0, 65535) as u16
+    }
+
+    #[inline(always)]
+    fn decode(self, lo: f32, hi: f32) -> f32 {
+        lo + self as f32 * (hi - lo) / 65535.0
+    }
+}
+
+// computed in f64, f32 can not hold the 32 and 64 bits maxima
+macro_rules! channel_int(
+    ($($t:ty),+) => ($(
+impl Channel for $t {
+    #[inline(always)]
+    fn encode(v: f32, lo: f32, hi: f32) -> $t {
+        let max = <$t>::max_value() as f64;
+        let x = ((v - lo) as f64 * max / (hi - lo) as f64).round();
+        x.max(<$t>::min_value() as f64).min(max) as $t
+    }
+
+    #[inline(always)]
+    fn decode(self, lo: f32, hi: f32) -> f32 {
+        lo + (self as f64 * (hi - lo) as f64 / <$t>::max_value() as f64) as f32
+    }
+}
+    )+);
+);
+
+channel_int!(u32, u64, usize, i8, i16, i32, i64, isize);
+
+impl Channel for f32 {
+    #[inline(always)]
+    fn encode(v: f32, _: f32, _: f32) -> f32 {
+        v
+    }
+
+    #[inline(always)]
+    fn decode(self, _: f32, _: f32) -> f32 {
+        self
+    }
+}
+
+impl Channel for f64 {
+    #[inline(always)]
+    fn encode(v: f32, _: f32, _: f32) -> f64 {
+        v as f64
+    }
+
+    #[inline(always)]
+    fn decode(self, _: f32, _: f32) -> f32 {
+        self as f32
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorModel {
+    Gray,
+    Bgr,
+    Hsv,
+    Hls,
+    YCrCb,
+    Xyz,
+    Lab,
+    Luv
+}
+
+impl ColorModel {
+    /// Range of canonical channel `i` covered by integer subpixels,
+    /// channel 3 is alpha.
+    fn range(self, i: usize) -> (f32, f32) {
+        match (self, i) {
+            (ColorModel::Hsv, 0) | (ColorModel::Hls, 0) => (0.0, 510.0),
+            (ColorModel::YCrCb, 1) | (ColorModel::YCrCb, 2) => {
+                (0.5 - 128.0 / 255.0, 0.5 + 127.0 / 255.0)
+            },
+            (ColorModel::Lab, 0) | (ColorModel::Luv, 0) => (0.0, 100.0),
+            (ColorModel::Lab, _) if i < 3 => (-128.0, 127.0),
+            (ColorModel::Luv, 1) => (-134.0, 220.0),
+            (ColorModel::Luv, 2) => (-140.0, 122.0),
+            _ => (0.0, 1.0)
+        }
+    }
+
+    fn to_bgra(self, v: [f32; 4]) -> [f32; 4] {
+        let p = [v[0], v[1], v[2]];
+        let bgr = match self {
+            ColorModel::Gray => [v[0], v[0], v[0]],
+            ColorModel::Bgr => p,
+            ColorModel::Hsv => hsv_to_bgr(p),
+            ColorModel::Hls => hls_to_bgr(p),
+            ColorModel::YCrCb => ycrcb_to_bgr(p, 0.5),
+            ColorModel::Xyz => xyz_to_bgr(p),
+            ColorModel::Lab => lab_to_bgr(p),
+            ColorModel::Luv => luv_to_bgr(p)
+        };
+        [bgr[0], bgr[1], bgr[2], v[3]]
+    }
+
+    fn from_bgra(self, v: [f32; 4]) -> [f32; 4] {
+        let p = [v[0], v[1], v[2]];
+        let t = match self {
+            ColorModel::Gray => [bgr_to_gray(p), 0.0, 0.0],
+            ColorModel::Bgr => p,
+            ColorModel::Hsv => bgr_to_hsv(p),
+            ColorModel::Hls => bgr_to_hls(p),
+            ColorModel::YCrCb => bgr_to_ycrcb(p, 0.5),
+            ColorModel::Xyz => bgr_to_xyz(p),
+            ColorModel::Lab => bgr_to_lab(p),
+            ColorModel::Luv => bgr_to_luv(p)
+        };
+        [t[0], t[1], t[2], v[3]]
+    }
+}
+
+/// Pixels that can be converted into each other with `FromPixel`.
+pub trait ColorPixel: Pixel {
+    fn model() -> ColorModel;
+
+    /// Returns the canonical channels of the color model plus alpha.
+    fn to_model(&self) -> [f32; 4];
+
+    fn from_model(v: [f32; 4]) -> Self;
+}
+
+macro_rules! color_pixel(
+    ($t:ident, $model:ident, [$($idx:expr),+]) => (
+impl<T: Channel> ColorPixel for $t<T> {
+    #[inline(always)]
+    fn model() -> ColorModel {
+        ColorModel::$model
+    }
+
+    #[inline]
+    fn to_model(&self) -> [f32; 4] {
+        let mut v = [0.0, 0.0, 0.0, 1.0];
+        for (c, &i) in [$($idx),+].iter().enumerate() {
+            let (lo, hi) = ColorModel::$model.range(i);
+            v[i] = self.data[c].decode(lo, hi);
+        }
+        v
+    }
+
+    #[inline]
+    fn from_model(v: [f32; 4]) -> Self {
+        let mut p = Self::zero();
+        for (c, &i) in [$($idx),+].iter().enumerate() {
+            let (lo, hi) = ColorModel::$model.range(i);
+            p.data[c] = T::encode(v[i], lo, hi);
+        }
+        p
+    }
+}
+    );
+);
+
+color_pixel!(Gray, Gray, [0]);
+color_pixel!(Bgr, Bgr, [0, 1, 2]);
+color_pixel!(Bgra, Bgr, [0, 1, 2, 3]);
+color_pixel!(Rgba, Bgr, [2, 1, 0, 3]);
+color_pixel!(Hsv, Hsv, [0, 1, 2]);
+color_pixel!(Hls, Hls, [0, 1, 2]);
+color_pixel!(YCrCb, YCrCb, [0, 1, 2]);
+color_pixel!(Xyz, Xyz, [0, 1, 2]);
+color_pixel!(Lab, Lab, [0, 1, 2]);
+color_pixel!(Luv, Luv, [0, 1, 2]);
+
+/// Conversion from pixel type `P`, implemented for every pair of color
+/// pixels. Pixels of the same color model are rescaled channel by
+/// channel, others go through BGRA.
+pub trait FromPixel<P> {
+    fn from_pixel(p: &P) -> Self;
+}
+
+impl<S: ColorPixel, D: ColorPixel> FromPixel<S> for D {
+    #[inline]
+    fn from_pixel(src: &S) -> D {
+        let v = src.to_model();
+        if S::model() == D::model() {
+            D::from_model(v)
+        } else {
+            D::from_model(D::model().from_bgra(S::model().to_bgra(v)))
+        }
+    }
+}
+
+/// The reverse of `FromPixel`, `let g: Gray<u8> = bgr.into_pixel()`.
+pub trait IntoPixel<P> {
+    fn into_pixel(self) -> P;
+}
+
+impl<S, D: FromPixel<S>> IntoPixel<D> for S {
+    #[inline]
+    fn into_pixel(self) -> D {
+        D::from_pixel(&self)
+    }
+}
+
 pub fn convert<M>(src: &Image<M::SrcType>) -> Image<M::DstType> 
     where M: ColorMapper {
+    convert_image::<M, _>(src)
+}
+
+/// Same as `convert`, for a region of interest.
+pub fn convert_view<M>(src: &ImageView<M::SrcType>) -> Image<M::DstType>
+    where M: ColorMapper {
+    convert_image::<M, _>(src)
+}
+
+fn convert_image<M, I>(src: &I) -> Image<M::DstType>
+    where M: ColorMapper,
+          I: GenericImage<Pixel=M::SrcType> {
     let mut dst = Image::new(src.width(), src.height());
-    for h in 0..src.height() {
-        let psrc = src.row(h);
-        let pdst = dst.row_mut(h);
-        for w in 0..src.width() as usize {
-            pdst[w] = M::to(&psrc[w]);
-        }
-    }
+    let width = src.width() as usize;
+    for_each_row_mut(&mut dst, |h, pdst| {
+        M::map_row(&src.row(h)[..width], &mut pdst[..width]);
+    });
     dst
 }
 
-pub fn split<T, U>(src: &Image<T>) -> Vec<Image<Gray<U>>> 
+pub fn split<T, U, I>(src: &I) -> Vec<Image<Gray<U>>> 
     where T: Pixel,
           U: Primitive,
-          T: Index<usize, Output=U>
+          T: Index<usize, Output=U>,
+          I: GenericImage<Pixel=T>
 {
-    let mut out = Vec::with_capacity(src.channels() as usize);
-    for _ in 0..src.channels() {
+    let mut out = Vec::with_capacity(T::channels());
+    for _ in 0..T::channels() {
         out.push(Image::<Gray<U>>::new(src.width(), src.height()));
     }
     // not the most efficiet way, but saving all out row ptr
     // will violate the borrow checker
     for y in 0..src.height() {
         let psrc = src.row(y);
-        for c in 0..src.channels() as usize {
+        for c in 0..T::channels() {
             let pdst = out[c].row_mut(y);
             for x in 0..src.width() as usize {
                 pdst[x][0] = psrc[x][c];
@@ -136,6 +739,7 @@ pub fn merge<P, T>(src: &Vec<Image<Gray<P>>>) -> Image<T>
 #[cfg(test)]
 mod test {
     use image::*;
+    use geo::Recti;
     use super::*;
 
     #[test]
@@ -149,9 +753,9 @@ mod test {
             r[3] = Bgra([255, 255, 255, 255]);
         }
         let dst = convert::<MapBgraGray>(&src);
-        assert_eq!(dst[(0, 0)], Gray([27]));
+        assert_eq!(dst[(0, 0)], Gray([28]));
         assert_eq!(dst[(1, 0)], Gray([150]));
-        assert_eq!(dst[(2, 0)], Gray([76]));
+        assert_eq!(dst[(2, 0)], Gray([77]));
         assert_eq!(dst[(3, 0)], Gray([255]));
     }
 
@@ -172,5 +776,174 @@ mod test {
             assert_eq!(*p, src[(x, y)]);
         }
     }
+
+    fn close(a: [f32; 3], b: [f32; 3], eps: f32) -> bool {
+        (0..3).all(|i| (a[i] - b[i]).abs() <= eps)
+    }
+
+    fn close_u8(a: [u8; 3], b: [u8; 3], eps: i32) -> bool {
+        (0..3).all(|i| (a[i] as i32 - b[i] as i32).abs() <= eps)
+    }
+
+    #[test]
+    fn test_color_spaces_u8() {
+        let red = Bgr([0u8, 0, 255]);
+        let blue = Bgr([255u8, 0, 0]);
+        let white = Bgr([255u8, 255, 255]);
+        assert_eq!(MapBgrHsv::to(&red), Hsv([0, 255, 255]));
+        assert_eq!(MapBgrHsv::to(&blue), Hsv([120, 255, 255]));
+        assert_eq!(MapBgrHls::to(&blue), Hls([120, 128, 255]));
+        assert_eq!(MapBgrYCrCb::to(&Bgr([128, 128, 128])), YCrCb([128, 128, 128]));
+        assert_eq!(MapBgrXyz::to(&white), Xyz([242, 255, 255]));
+        assert_eq!(MapBgrLab::to(&white), Lab([255, 128, 128]));
+        assert_eq!(MapBgrLab::to(&Bgr([0, 0, 0])), Lab([0, 128, 128]));
+        assert_eq!(MapBgrLuv::to(&white).data[0], 255);
+
+        for &b in [0u8, 17, 128, 200, 255].iter() {
+            for &g in [0u8, 60, 128, 255].iter() {
+                for &r in [0u8, 99, 255].iter() {
+                    let p = Bgr([b, g, r]);
+                    // 8-bit hue has a 2 degree step
+                    assert!(close_u8(MapHsvBgr::to(&MapBgrHsv::to(&p)).data, p.data, 6));
+                    assert!(close_u8(MapHlsBgr::to(&MapBgrHls::to(&p)).data, p.data, 6));
+                    assert!(close_u8(MapYCrCbBgr::to(&MapBgrYCrCb::to(&p)).data, p.data, 2));
+                }
+            }
+        }
+        // 8-bit L*a*b* and L*u*v* are too coarse to restore channels close
+        // to zero of saturated colors, check mid-tones only
+        for &b in [60u8, 128, 200].iter() {
+            for &g in [60u8, 99, 200].iter() {
+                for &r in [60u8, 128, 200].iter() {
+                    let p = Bgr([b, g, r]);
+                    assert!(close_u8(MapLabBgr::to(&MapBgrLab::to(&p)).data, p.data, 3));
+                    assert!(close_u8(MapLuvBgr::to(&MapBgrLuv::to(&p)).data, p.data, 3));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_color_spaces_f32() {
+        let red = Bgr([0f32, 0.0, 1.0]);
+        assert!(close(MapBgrfHsvf::to(&red).data, [0.0, 1.0, 1.0], 1e-6));
+        assert!(close(MapBgrfHlsf::to(&Bgr([0.0, 1.0, 0.0])).data, [120.0, 0.5, 1.0], 1e-6));
+        assert!(close(MapBgrfLabf::to(&Bgr([1.0, 1.0, 1.0])).data, [100.0, 0.0, 0.0], 1e-2));
+        assert!(close(MapBgrfLuvf::to(&Bgr([1.0, 1.0, 1.0])).data, [100.0, 0.0, 0.0], 1e-2));
+        assert!(close(MapBgrfYCrCbf::to(&Bgr([0.5, 0.5, 0.5])).data, [0.5, 0.5, 0.5], 1e-6));
+
+        for &b in [0f32, 0.1, 0.5, 1.0].iter() {
+            for &g in [0f32, 0.3, 0.8].iter() {
+                for &r in [0f32, 0.25, 0.6, 1.0].iter() {
+                    let p = Bgr([b, g, r]);
+                    assert!(close(MapHsvfBgrf::to(&MapBgrfHsvf::to(&p)).data, p.data, 1e-4));
+                    assert!(close(MapHlsfBgrf::to(&MapBgrfHlsf::to(&p)).data, p.data, 1e-4));
+                    assert!(close(MapYCrCbfBgrf::to(&MapBgrfYCrCbf::to(&p)).data, p.data, 2e-3));
+                    assert!(close(MapXyzfBgrf::to(&MapBgrfXyzf::to(&p)).data, p.data, 1e-3));
+                    assert!(close(MapLabfBgrf::to(&MapBgrfLabf::to(&p)).data, p.data, 1e-3));
+                    assert!(close(MapLuvfBgrf::to(&MapBgrfLuvf::to(&p)).data, p.data, 1e-3));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_convert_view() {
+        let mut src = ImageBgr::new(4, 4);
+        src.fill(&Bgr([0, 0, 0]));
+        src.view_mut(&Recti::new(1, 1, 2, 2)).fill(&Bgr([255, 255, 255]));
+        let dst = convert_view::<MapBgrGray>(&src.view(&Recti::new(1, 0, 2, 2)));
+        assert_eq!(dst.size(), (2, 2));
+        assert_eq!(dst.row(0), &[Gray([0]), Gray([0])]);
+        assert_eq!(dst.row(1), &[Gray([255]), Gray([255])]);
+    }
+
+    #[test]
+    fn test_from_pixel() {
+        // channel order and alpha
+        assert_eq!(Bgra::from_pixel(&Bgr([1u8, 2, 3])), Bgra([1u8, 2, 3, 255]));
+        assert_eq!(Bgr::from_pixel(&Bgra([1u8, 2, 3, 4])), Bgr([1u8, 2, 3]));
+        assert_eq!(Rgba::from_pixel(&Bgra([1u8, 2, 3, 4])), Rgba([3u8, 2, 1, 4]));
+        assert_eq!(Bgra::from_pixel(&Rgba([1u8, 2, 3, 4])), Bgra([3u8, 2, 1, 4]));
+        assert_eq!(Bgra::from_pixel(&Gray([7u8])), Bgra([7u8, 7, 7, 255]));
+        assert_eq!(Gray::from_pixel(&Bgr([9u8, 9, 9])), Gray([9u8]));
+
+        // subpixel ranges
+        assert_eq!(Gray::from_pixel(&Gray([65535u16])), Gray([255u8]));
+        assert_eq!(Gray::from_pixel(&Gray([257u16 * 100])), Gray([100u8]));
+        assert_eq!(Gray::from_pixel(&Gray([255u8])), Gray([65535u16]));
+        assert_eq!(Gray::from_pixel(&Gray([51u8])), Gray([0.2f32]));
+        assert_eq!(Gray::from_pixel(&Gray([0.5f32])), Gray([128u8]));
+        assert_eq!(Gray::from_pixel(&Gray([1.5f32])), Gray([255u8]));
+        assert_eq!(Bgra::from_pixel(&Bgr([0.0f32, 1.0, 0.0])), Bgra([0u16, 65535, 0, 65535]));
+        let g: Gray<u8> = Gray([1.0f64]).into_pixel();
+        assert_eq!(g, Gray([255]));
+        assert_eq!(Gray::from_pixel(&Gray([255u8])), Gray([32767i16]));
+        assert_eq!(Gray::from_pixel(&Gray([0.5f32])), Gray([16384i16]));
+        assert_eq!(Gray::from_pixel(&Gray([-32767i16])), Gray([-1.0f32]));
+        assert_eq!(Gray::from_pixel(&Gray([-5i16])), Gray([0u8]));
+        assert_eq!(Gray::from_pixel(&Gray([u32::max_value()])), Gray([255u8]));
+        assert_eq!(Gray::from_pixel(&Gray([1.0f32])), Gray([i64::max_value()]));
+        assert_eq!(Bgr::from_pixel(&Bgr([127i8, 0, 127])), Bgr([255u8, 0, 255]));
+
+        // same model, different subpixels
+        assert_eq!(Hsv::from_pixel(&Hsv([90u8, 255, 51])), Hsv([180.0f32, 1.0, 0.2]));
+        assert_eq!(Lab::from_pixel(&Lab([50.0f32, -28.0, 72.0])), Lab([128u8, 100, 200]));
+        assert_eq!(YCrCb::from_pixel(&YCrCb([0.0f32, 0.5, 0.5])), YCrCb([0u8, 128, 128]));
+        let p = Luv([10u8, 20, 30]);
+        assert_eq!(Luv::from_pixel(&Luv::<f32>::from_pixel(&p)), p);
+
+        // across models the result matches the mappers
+        let close = |a: [u8; 3], b: [u8; 3]| {
+            (0..3).all(|i| (a[i] as i32 - b[i] as i32).abs() <= 1)
+        };
+        for &c in [[0u8, 0, 0], [255, 255, 255], [30, 160, 220], [200, 40, 90]].iter() {
+            let bgr = Bgr(c);
+            assert!(close(Hsv::from_pixel(&bgr).data, MapBgrHsv::to(&bgr).data));
+            assert!(close(Hls::from_pixel(&bgr).data, MapBgrHls::to(&bgr).data));
+            assert!(close(YCrCb::from_pixel(&bgr).data, MapBgrYCrCb::to(&bgr).data));
+            assert!(close(Lab::from_pixel(&bgr).data, MapBgrLab::to(&bgr).data));
+            assert!(close(Luv::from_pixel(&bgr).data, MapBgrLuv::to(&bgr).data));
+            let g: Gray<u8> = Hsv::<f32>::from_pixel(&bgr).into_pixel();
+            assert!((g[0] as i32 - MapBgrGray::to(&bgr)[0] as i32).abs() <= 1);
+        }
+    }
+
+    #[test]
+    fn test_gray_agrees() {
+        // one definition of gray for the mappers, FromPixel and the row kernels
+        let mut pixels = Vec::new();
+        for b in 0..256 {
+            for g in (0..256).step_by(3) {
+                for r in (0..256).step_by(5) {
+                    pixels.push(Bgra([b as u8, g as u8, r as u8, 255]));
+                }
+            }
+        }
+        let src = Image::from_vec(pixels.len() as u32, 1, pixels.len() as u32, pixels).unwrap();
+        let gray = convert::<MapBgraGray>(&src);
+        for ((_, _, p), (_, _, g)) in src.iter().zip(gray.iter()) {
+            let bgr = Bgr([p[0], p[1], p[2]]);
+            assert_eq!(*g, MapBgraGray::to(p));
+            assert_eq!(*g, MapBgrGray::to(&bgr));
+            assert_eq!(*g, Gray::from_pixel(&bgr));
+            assert_eq!(*g, Gray::from_pixel(p));
+        }
+        assert_eq!(MapBgrGray::to(&Bgr([0, 0, 255])), Gray([77]));
+        assert_eq!(MapBgrGray::to(&Bgr([255, 255, 255])), Gray([255]));
+    }
+
+    #[test]
+    fn test_image_convert() {
+        let src = ImageBgr::from_fn(4, 3, |x, y| Bgr([x as u8 * 60, y as u8 * 100, 255]));
+        let dst = src.convert::<Bgra<u8>>();
+        assert_eq!(dst.size(), (4, 3));
+        assert_eq!(dst[(3, 2)], Bgra([180, 200, 255, 255]));
+        let dst = src.view(&Recti::new(1, 1, 2, 2)).convert::<Bgr<f32>>();
+        assert_eq!(dst.size(), (2, 2));
+        assert_eq!(dst[(0, 0)], Bgr([60.0 / 255.0, 100.0 / 255.0, 1.0]));
+        let back = dst.convert::<Bgr<u8>>();
+        assert_eq!(back[(1, 1)], src[(2, 2)]);
+    }
 }
 
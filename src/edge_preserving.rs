@@ -0,0 +1,216 @@
+//! Edge-preserving smoothing: bilateral and guided filters.
+
+use image::*;
+use num::traits::ToPrimitive;
+use traits::SaturateCast;
+use border::{BorderType, border_index, border_value};
+use integral::blur;
+use par::for_each_row_mut;
+
+/// Averages the pixels of a disc of diameter `d` weighted by a gaussian of
+/// their distance (`sigma_space`) and of their color difference
+/// (`sigma_color`, in pixel units), so strong edges are not smoothed. A
+/// non-positive `d` is derived from `sigma_space`.
+pub fn bilateral_filter<T, I>(src: &I, d: i32, sigma_color: f32, sigma_space: f32,
+                              border: BorderType) -> Image<T>
+    where T: Pixel,
+          I: GenericImage<Pixel=T>
+{
+    assert!(sigma_color > 0.0 && sigma_space > 0.0);
+    let r = if d <= 0 { (sigma_space * 1.5).round() as i32 } else { d / 2 };
+    let (w, h) = (src.width() as i32, src.height() as i32);
+    let channels = T::channels();
+    let cval: f32 = border_value(border);
+
+    let mut taps = Vec::new();
+    for dy in -r..r + 1 {
+        for dx in -r..r + 1 {
+            let d2 = (dx * dx + dy * dy) as f32;
+            if d2 <= (r * r) as f32 {
+                taps.push((dx, dy, (-d2 / (2.0 * sigma_space * sigma_space)).exp()));
+            }
+        }
+    }
+    let color_coeff = -1.0 / (2.0 * sigma_color * sigma_color);
+
+    let mut dst: Image<T> = Image::new(w as u32, h as u32);
+    for_each_row_mut(&mut dst, |y, pdst| {
+        let y = y as i32;
+        for x in 0..w {
+            let center = src.row(y as u32)[x as usize];
+            let mut sum = [0f32; MAX_CHANNEL_COUNT];
+            let mut wsum = 0f32;
+            for &(dx, dy, ws) in taps.iter() {
+                let mut v = [cval; MAX_CHANNEL_COUNT];
+                if let (Some(sx), Some(sy)) = (border_index(x + dx, w, border),
+                                               border_index(y + dy, h, border)) {
+                    let p = src.row(sy as u32)[sx as usize];
+                    for c in 0..channels {
+                        v[c] = p.raw()[c].to_f32().unwrap();
+                    }
+                }
+                let mut diff = 0f32;
+                for c in 0..channels {
+                    let t = v[c] - center.raw()[c].to_f32().unwrap();
+                    diff += t * t;
+                }
+                let wt = ws * (diff * color_coeff).exp();
+                for c in 0..channels {
+                    sum[c] += v[c] * wt;
+                }
+                wsum += wt;
+            }
+            for c in 0..channels {
+                pdst[x as usize].raw_mut()[c] = SaturateCast::saturate_cast(sum[c] / wsum);
+            }
+        }
+    });
+    dst
+}
+
+fn channel<P, I>(src: &I, c: usize) -> ImageGrayf
+    where P: Pixel,
+          I: GenericImage<Pixel=P>
+{
+    let mut dst = ImageGrayf::new(src.width(), src.height());
+    for y in 0..src.height() {
+        for (d, s) in dst.row_mut(y).iter_mut().zip(src.row(y).iter()) {
+            *d = Gray([s.raw()[c].to_f32().unwrap()]);
+        }
+    }
+    dst
+}
+
+fn zip_with<F>(a: &ImageGrayf, b: &ImageGrayf, f: F) -> ImageGrayf
+    where F: Fn(f32, f32) -> f32 + Sync + Send
+{
+    let mut dst = ImageGrayf::new(a.width(), a.height());
+    for_each_row_mut(&mut dst, |y, pdst| {
+        for ((d, p), q) in pdst.iter_mut().zip(a.row(y).iter()).zip(b.row(y).iter()) {
+            *d = Gray([f(p[0], q[0])]);
+        }
+    });
+    dst
+}
+
+/// He et al. guided filter, smooths `src` while keeping the edges of
+/// `guide` within windows of `2 * radius + 1` pixels. `eps` regularizes
+/// in squared pixel units, larger values smooth more. A single channel
+/// guide applies to all channels of `src`, otherwise channel `c` of `src`
+/// is guided by channel `c` of `guide`. Pass `src` as the guide for plain
+/// edge-preserving smoothing.
+pub fn guided_filter<T, G, I, J>(src: &I, guide: &J, radius: u32, eps: f32,
+                                 border: BorderType) -> Image<T>
+    where T: Pixel,
+          G: Pixel,
+          I: GenericImage<Pixel=T>,
+          J: GenericImage<Pixel=G>
+{
+    assert_eq!(src.size(), guide.size());
+    assert!(G::channels() == 1 || G::channels() == T::channels(),
+            "the guide must have 1 or {} channels", T::channels());
+    let k = 2 * radius + 1;
+    let mut dst: Image<T> = Image::new(src.width(), src.height());
+    for c in 0..T::channels() {
+        let g = channel(guide, if G::channels() == 1 { 0 } else { c });
+        let p = channel(src, c);
+        let mean_g: ImageGrayf = blur(&g, k, k, border);
+        let mean_p: ImageGrayf = blur(&p, k, k, border);
+        let corr_gg: ImageGrayf = blur(&zip_with(&g, &g, |a, b| a * b), k, k, border);
+        let corr_gp: ImageGrayf = blur(&zip_with(&g, &p, |a, b| a * b), k, k, border);
+
+        // q = a * g + b, fitted in every window
+        let var_g = zip_with(&corr_gg, &mean_g, |c, m| c - m * m);
+        let cov_gp = zip_with(&corr_gp, &zip_with(&mean_g, &mean_p, |a, b| a * b), |c, m| c - m);
+        let a = zip_with(&cov_gp, &var_g, |c, v| c / (v + eps));
+        let b = zip_with(&mean_p, &zip_with(&a, &mean_g, |a, m| a * m), |m, am| m - am);
+        let mean_a: ImageGrayf = blur(&a, k, k, border);
+        let mean_b: ImageGrayf = blur(&b, k, k, border);
+        let q = zip_with(&zip_with(&mean_a, &g, |a, g| a * g), &mean_b, |ag, b| ag + b);
+
+        for y in 0..src.height() {
+            for (d, s) in dst.row_mut(y).iter_mut().zip(q.row(y).iter()) {
+                d.raw_mut()[c] = SaturateCast::saturate_cast(s[0]);
+            }
+        }
+    }
+    dst
+}
+
+#[cfg(test)]
+mod test {
+    use image::*;
+    use border::BorderType;
+    use conv::gaussian_blur;
+    use super::*;
+
+    // a step from 50 to 200 at x = 8 with a little noise
+    fn step() -> ImageGray {
+        ImageGray::from_fn(16, 12, |x, y| {
+            let noise = ((x * 7 + y * 13) % 5) as u8;
+            Gray([if x < 8 { 50 + noise } else { 200 + noise }])
+        })
+    }
+
+    fn mean_abs_diff(a: &ImageGray, b: &ImageGray) -> f32 {
+        let n = (a.width() * a.height()) as f32;
+        a.iter().zip(b.iter())
+            .fold(0f32, |s, ((_, _, p), (_, _, q))| s + (p[0] as f32 - q[0] as f32).abs()) / n
+    }
+
+    #[test]
+    fn test_bilateral_filter() {
+        let flat = ImageBgr::from_pixel(6, 6, Bgr([10, 20, 30]));
+        let out = bilateral_filter(&flat, 5, 20.0, 3.0, BorderType::Reflect101);
+        assert!(out.iter().all(|(_, _, p)| *p == Bgr([10, 20, 30])));
+
+        let src = step();
+        let out = bilateral_filter(&src, 5, 20.0, 3.0, BorderType::Replicate);
+        // the edge survives while the noise is smoothed
+        assert!((0..12).all(|y| out[(7, y)][0] < 60 && out[(8, y)][0] > 190));
+        let blurred = gaussian_blur(&src, 5, 0.0);
+        assert!(mean_abs_diff(&out, &src) < mean_abs_diff(&blurred, &src));
+        let spread = |img: &ImageGray| {
+            let v: Vec<u8> = (0..6).map(|x| img[(x, 5)][0]).collect();
+            v.iter().max().unwrap() - v.iter().min().unwrap()
+        };
+        assert!(spread(&out) < spread(&src));
+
+        // a huge color sigma degrades to a gaussian blur
+        let srcf = Image::from_fn(8, 8, |x, _| Gray([if x < 4 { 0.0f32 } else { 1.0 }]));
+        let out = bilateral_filter(&srcf, 3, 1e6, 1.0, BorderType::Replicate);
+        assert!(out[(3, 0)][0] > 0.1 && out[(4, 0)][0] < 0.9);
+    }
+
+    #[test]
+    fn test_guided_filter() {
+        let flat = ImageGrayf::from_pixel(7, 7, Gray([0.25]));
+        let out = guided_filter(&flat, &flat, 2, 0.01, BorderType::Reflect);
+        assert!(out.iter().all(|(_, _, p)| (p[0] - 0.25).abs() < 1e-5));
+
+        let src = step();
+        let out = guided_filter(&src, &src, 2, 100.0, BorderType::Reflect);
+        assert!((0..12).all(|y| out[(7, y)][0] < 60 && out[(8, y)][0] > 190));
+        let spread = |img: &ImageGray| {
+            let v: Vec<u8> = (0..6).map(|x| img[(x, 5)][0]).collect();
+            v.iter().max().unwrap() - v.iter().min().unwrap()
+        };
+        assert!(spread(&out) <= spread(&src));
+
+        // a huge eps degrades to a box blur of a box blur
+        let out = guided_filter(&src, &src, 1, 1e9, BorderType::Reflect);
+        let srcf = channel(&src, 0);
+        let boxed = blur(&blur(&srcf, 3, 3, BorderType::Reflect), 3, 3, BorderType::Reflect);
+        assert!(out.iter().zip(boxed.iter()).all(|((_, _, p), (_, _, q))| {
+            (p[0] as f32 - q[0]).abs() < 1.0
+        }));
+
+        // a gray guide for a color image
+        let bgr = Image::from_fn(16, 12, |x, y| {
+            let g = src[(x, y)][0];
+            Bgr([g, g / 2, 255 - g])
+        });
+        let out = guided_filter(&bgr, &src, 2, 100.0, BorderType::Reflect);
+        assert!((0..12).all(|y| out[(7, y)][2] > 195 && out[(8, y)][2] < 65));
+    }
+}
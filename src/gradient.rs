@@ -0,0 +1,310 @@
+//! Image derivatives built on `conv2d_sep` and the Canny edge detector.
+
+use image::*;
+use traits::Primitive;
+use border::BorderType;
+use conv::conv2d_sep_to;
+use par::for_each_row_mut;
+
+// 1D kernel of derivative `order` with `ksize` taps, binomial smoothing
+// convolved with `order` differences
+fn deriv_kernel(order: u32, ksize: u32) -> Vec<f32> {
+    let mut k = vec![1f32];
+    for i in 0..ksize - 1 {
+        let d = if i < ksize - 1 - order { 1.0 } else { -1.0 };
+        let mut t = vec![0f32; k.len() + 1];
+        for j in 0..k.len() {
+            t[j] += k[j] * d;
+            t[j + 1] += k[j];
+        }
+        k = t;
+    }
+    k
+}
+
+// (kernelx, kernely) of a Sobel operator
+fn sobel_kernels(dx: u32, dy: u32, ksize: u32) -> (Vec<f32>, Vec<f32>) {
+    assert!(ksize == 1 || ksize == 3 || ksize == 5 || ksize == 7,
+            "invalid Sobel kernel size {}", ksize);
+    assert!(dx + dy > 0, "no derivative requested");
+    let kernel = |order: u32| {
+        if ksize == 1 {
+            assert!(order <= 2, "derivative order {} too high for ksize 1", order);
+            if order == 0 { vec![1.0] } else { deriv_kernel(order, 3) }
+        } else {
+            assert!(order < ksize, "derivative order {} too high for ksize {}", order, ksize);
+            deriv_kernel(order, ksize)
+        }
+    };
+    (kernel(dx), kernel(dy))
+}
+
+/// Derivative of order `dx` in x and `dy` in y, smoothed in the other
+/// direction by a `ksize` x `ksize` Sobel kernel. `ksize` 1 uses a 3
+/// tap derivative without smoothing.
+pub fn sobel<S, I>(src: &I, dx: u32, dy: u32, ksize: u32, border: BorderType) -> ImageGrayf
+    where S: Primitive,
+          I: GenericImage<Pixel=Gray<S>>
+{
+    let (kx, ky) = sobel_kernels(dx, dy, ksize);
+    conv2d_sep_to(src, &kx, &ky, 1.0, 0.0, border)
+}
+
+/// First derivative in x (`dx` = 1) or y (`dy` = 1) with the 3x3 Scharr
+/// kernel, which is more rotation invariant than the 3x3 Sobel.
+pub fn scharr<S, I>(src: &I, dx: u32, dy: u32, border: BorderType) -> ImageGrayf
+    where S: Primitive,
+          I: GenericImage<Pixel=Gray<S>>
+{
+    assert!(dx + dy == 1, "Scharr computes first derivatives only");
+    let deriv = [-1.0, 0.0, 1.0];
+    let smooth = [3.0, 10.0, 3.0];
+    if dx == 1 {
+        conv2d_sep_to(src, &deriv, &smooth, 1.0, 0.0, border)
+    } else {
+        conv2d_sep_to(src, &smooth, &deriv, 1.0, 0.0, border)
+    }
+}
+
+/// Sum of the second derivatives in x and y, computed with Sobel kernels
+/// of size `ksize`. `ksize` 1 is the 4-neighbour Laplacian.
+pub fn laplacian<S, I>(src: &I, ksize: u32, border: BorderType) -> ImageGrayf
+    where S: Primitive,
+          I: GenericImage<Pixel=Gray<S>>
+{
+    let mut dst = sobel(src, 2, 0, ksize, border);
+    let d2y = sobel(src, 0, 2, ksize, border);
+    for_each_row_mut(&mut dst, |y, pdst| {
+        for (a, b) in pdst.iter_mut().zip(d2y.row(y).iter()) {
+            a[0] += b[0];
+        }
+    });
+    dst
+}
+
+/// Returns the magnitude and the orientation in degrees [0, 360) of the
+/// 3x3 Sobel gradient.
+pub fn gradients<S, I>(src: &I) -> (ImageGrayf, ImageGrayf)
+    where S: Primitive,
+          I: GenericImage<Pixel=Gray<S>>
+{
+    let gx = sobel(src, 1, 0, 3, BorderType::default());
+    let gy = sobel(src, 0, 1, 3, BorderType::default());
+    let (w, h) = gx.size();
+    let mut mag = ImageGrayf::new(w, h);
+    let mut angle = ImageGrayf::new(w, h);
+    for_each_row_mut(&mut mag, |y, pdst| {
+        for ((m, a), b) in pdst.iter_mut().zip(gx.row(y).iter()).zip(gy.row(y).iter()) {
+            m[0] = a[0].hypot(b[0]);
+        }
+    });
+    for_each_row_mut(&mut angle, |y, pdst| {
+        for ((t, a), b) in pdst.iter_mut().zip(gx.row(y).iter()).zip(gy.row(y).iter()) {
+            let deg = b[0].atan2(a[0]).to_degrees();
+            t[0] = if deg < 0.0 { deg + 360.0 } else { deg };
+        }
+    });
+    (mag, angle)
+}
+
+/// Canny edge detector, returns 255 on edge pixels and 0 elsewhere.
+/// Pixels with a gradient magnitude above `high` start edges, which are
+/// followed through pixels above `low`. The magnitude is `|dx| + |dy|`
+/// of the 3x3 Sobel, or the euclidean norm with `l2_gradient`.
+pub fn canny(src: &ImageGray, low: f32, high: f32, l2_gradient: bool) -> ImageGray {
+    let (low, high) = if low > high { (high, low) } else { (low, high) };
+    let gx = sobel(src, 1, 0, 3, BorderType::default());
+    let gy = sobel(src, 0, 1, 3, BorderType::default());
+    let (w, h) = (src.width() as usize, src.height() as usize);
+
+    let mut mag = vec![0f32; w * h];
+    for y in 0..h {
+        let (px, py) = (gx.row(y as u32), gy.row(y as u32));
+        for x in 0..w {
+            let (dx, dy) = (px[x][0], py[x][0]);
+            mag[y * w + x] = if l2_gradient { dx.hypot(dy) } else { dx.abs() + dy.abs() };
+        }
+    }
+    let m = |x: isize, y: isize| {
+        if x < 0 || y < 0 || x >= w as isize || y >= h as isize {
+            0.0
+        } else {
+            mag[y as usize * w + x as usize]
+        }
+    };
+
+    // non-maximum suppression along the gradient direction, quantized to
+    // 45 degrees
+    let tan22 = 0.4142136f32;
+    let tan67 = 2.4142136f32;
+    let mut stack = Vec::new();
+    let mut state = vec![0u8; w * h];
+    const CANDIDATE: u8 = 1;
+    const EDGE: u8 = 2;
+    for y in 0..h {
+        let (px, py) = (gx.row(y as u32), gy.row(y as u32));
+        for x in 0..w {
+            let v = mag[y * w + x];
+            if v <= low {
+                continue;
+            }
+            let (dx, dy) = (px[x][0], py[x][0]);
+            let (ax, ay) = (dx.abs(), dy.abs());
+            let (xi, yi) = (x as isize, y as isize);
+            let (n1, n2) = if ay <= ax * tan22 {
+                (m(xi - 1, yi), m(xi + 1, yi))
+            } else if ay >= ax * tan67 {
+                (m(xi, yi - 1), m(xi, yi + 1))
+            } else if (dx > 0.0) == (dy > 0.0) {
+                (m(xi - 1, yi - 1), m(xi + 1, yi + 1))
+            } else {
+                (m(xi + 1, yi - 1), m(xi - 1, yi + 1))
+            };
+            // ties are broken towards the lower coordinate
+            if v > n1 && v >= n2 {
+                if v > high {
+                    state[y * w + x] = EDGE;
+                    stack.push((x, y));
+                } else {
+                    state[y * w + x] = CANDIDATE;
+                }
+            }
+        }
+    }
+
+    // hysteresis, grow the strong edges through 8-connected candidates
+    while let Some((x, y)) = stack.pop() {
+        for ny in y.saturating_sub(1)..(y + 2).min(h) {
+            for nx in x.saturating_sub(1)..(x + 2).min(w) {
+                if state[ny * w + nx] == CANDIDATE {
+                    state[ny * w + nx] = EDGE;
+                    stack.push((nx, ny));
+                }
+            }
+        }
+    }
+
+    let mut dst = ImageGray::new(w as u32, h as u32);
+    for y in 0..h {
+        for (d, s) in dst.row_mut(y as u32).iter_mut().zip(state[y * w..].iter()) {
+            *d = Gray([if *s == EDGE { 255 } else { 0 }]);
+        }
+    }
+    dst
+}
+
+#[cfg(test)]
+mod test {
+    use image::*;
+    use border::BorderType;
+    use super::*;
+
+    // the interior of the image, where the border does not matter
+    fn interior(img: &ImageGrayf, m: u32) -> Vec<f32> {
+        let mut out = Vec::new();
+        for y in m..img.height() - m {
+            for x in m..img.width() - m {
+                out.push(img[(x, y)][0]);
+            }
+        }
+        out
+    }
+
+    fn all_eq(v: &[f32], e: f32) -> bool {
+        v.iter().all(|a| (a - e).abs() < 1e-3)
+    }
+
+    #[test]
+    fn test_kernels() {
+        assert_eq!(deriv_kernel(0, 3), vec![1.0, 2.0, 1.0]);
+        assert_eq!(deriv_kernel(1, 3), vec![-1.0, 0.0, 1.0]);
+        assert_eq!(deriv_kernel(2, 3), vec![1.0, -2.0, 1.0]);
+        assert_eq!(deriv_kernel(1, 5), vec![-1.0, -2.0, 0.0, 2.0, 1.0]);
+        assert_eq!(sobel_kernels(1, 0, 1), (vec![-1.0, 0.0, 1.0], vec![1.0]));
+    }
+
+    #[test]
+    fn test_sobel() {
+        let ramp = ImageGray::from_fn(12, 10, |x, y| Gray([(3 * x + 5 * y) as u8]));
+        let b = BorderType::Replicate;
+        assert!(all_eq(&interior(&sobel(&ramp, 1, 0, 3, b), 1), 24.0));
+        assert!(all_eq(&interior(&sobel(&ramp, 0, 1, 3, b), 1), 40.0));
+        assert!(all_eq(&interior(&sobel(&ramp, 1, 0, 1, b), 1), 6.0));
+        assert!(all_eq(&interior(&sobel(&ramp, 1, 0, 5, b), 2), 16.0 * 8.0 * 3.0));
+        assert!(all_eq(&interior(&sobel(&ramp, 1, 1, 3, b), 1), 0.0));
+        assert!(all_eq(&interior(&scharr(&ramp, 1, 0, b), 1), 96.0));
+        assert!(all_eq(&interior(&scharr(&ramp, 0, 1, b), 1), 160.0));
+
+        // a constant image has no gradient whatever the border
+        let flat = ImageGrayf::from_pixel(5, 5, Gray([2.0]));
+        assert!(all_eq(&interior(&sobel(&flat, 1, 0, 3, BorderType::Reflect101), 0), 0.0));
+    }
+
+    #[test]
+    fn test_laplacian() {
+        let paraboloid = ImageGrayf::from_fn(9, 9, |x, y| {
+            let (x, y) = (x as f32 - 4.0, y as f32 - 4.0);
+            Gray([x * x + y * y])
+        });
+        let b = BorderType::Reflect;
+        assert!(all_eq(&interior(&laplacian(&paraboloid, 1, b), 1), 4.0));
+        assert!(all_eq(&interior(&laplacian(&paraboloid, 3, b), 1), 16.0));
+    }
+
+    #[test]
+    fn test_gradients() {
+        let ramp = ImageGray::from_fn(8, 8, |x, y| Gray([(3 * x + 5 * y) as u8]));
+        let (mag, angle) = gradients(&ramp);
+        assert!(all_eq(&interior(&mag, 1), 24f32.hypot(40.0)));
+        assert!(all_eq(&interior(&angle, 1), 40f32.atan2(24.0).to_degrees()));
+
+        let down = ImageGray::from_fn(8, 8, |_, y| Gray([(64 - 8 * y) as u8]));
+        let (_, angle) = gradients(&down);
+        assert!(all_eq(&interior(&angle, 1), 270.0));
+    }
+
+    fn edges(img: &ImageGray) -> Vec<(u32, u32)> {
+        img.iter().filter(|&(_, _, p)| p[0] != 0).map(|(x, y, _)| (x, y)).collect()
+    }
+
+    #[test]
+    fn test_canny() {
+        let square = ImageGray::from_fn(20, 20, |x, y| {
+            Gray([if x >= 5 && x < 15 && y >= 5 && y < 15 { 200 } else { 0 }])
+        });
+        for &l2 in [false, true].iter() {
+            let out = canny(&square, 100.0, 300.0, l2);
+            assert!(out.iter().all(|(_, _, p)| p[0] == 0 || p[0] == 255));
+            // one pixel wide edges on both sides of the square
+            let row: Vec<u32> = (0..20).filter(|&x| out[(x, 10)][0] != 0).collect();
+            assert_eq!(row, vec![4, 14]);
+            let col: Vec<u32> = (0..20).filter(|&y| out[(10, y)][0] != 0).collect();
+            assert_eq!(col, vec![4, 14]);
+            assert!(edges(&out).iter().all(|&(x, y)| x >= 3 && x <= 15 && y >= 3 && y <= 15));
+        }
+        assert!(edges(&canny(&square, 100.0, 2000.0, false)).is_empty());
+        // swapped thresholds
+        assert_eq!(edges(&canny(&square, 300.0, 100.0, false)),
+                   edges(&canny(&square, 100.0, 300.0, false)));
+    }
+
+    #[test]
+    fn test_canny_hysteresis() {
+        // a step of 200 in the upper half and 40 in the lower half
+        let step = ImageGray::from_fn(20, 20, |x, y| {
+            Gray([if x < 5 { 0 } else if y < 10 { 200 } else { 40 }])
+        });
+        // weak edges connected to strong ones are kept
+        let out = canny(&step, 100.0, 300.0, false);
+        assert!((0..8).chain(12..20).all(|y| out[(4, y)][0] == 255));
+        let out = canny(&step, 200.0, 300.0, false);
+        assert!((0..8).all(|y| out[(4, y)][0] == 255));
+        assert!((12..20).all(|y| out[(4, y)][0] == 0));
+
+        // isolated weak edges are dropped
+        let weak = ImageGray::from_fn(20, 20, |x, _| Gray([if x < 5 { 0 } else { 40 }]));
+        assert!(edges(&canny(&weak, 100.0, 300.0, false)).is_empty());
+        assert_eq!(edges(&canny(&weak, 100.0, 150.0, false)).len(), 20);
+    }
+}
+
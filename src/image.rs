@@ -1,10 +1,24 @@
+use std::alloc::{self, Layout};
+use std::mem;
+use std::ptr;
 use std::slice;
+use std::fmt;
+use std::io;
+use std::error::Error;
+use std::path::{Path, PathBuf};
 use num::NumCast;
 use num::traits::{Saturating, Bounded};
-use std::ops::{Index, IndexMut};
+use std::ops::{Deref, DerefMut, Index, IndexMut};
 use std::ops::{Add, Sub, Mul};
 
-use traits::Primitive;
+use traits::{Primitive, MaybeSync};
+use geo::{Pointi, Recti};
+use convert::FromPixel;
+use par::for_each_row_mut;
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+#[cfg(feature = "parallel")]
+use rayon::slice::ChunksMut;
 
 #[derive(Debug)]
 pub enum ImageError {
@@ -13,6 +27,74 @@ pub enum ImageError {
     OutOfMemoryError,
     UnknownImageFormat,
     UnknownError,
+    /// Reading or writing failed, e.g. a missing file
+    Io(io::Error),
+    /// The codec of `format` failed, with the message of the library if it
+    /// gave one
+    Codec { format: String, message: Option<String> },
+    /// Images with this many bits per pixel can not be converted to the
+    /// requested pixel type
+    UnsupportedBitDepth(u32),
+    /// A save option is out of the range the format accepts
+    InvalidOption(String),
+    /// `error` occurred on the file at `path`
+    File { path: PathBuf, error: Box<ImageError> },
+}
+
+impl ImageError {
+    /// Attaches the path of the file the error occurred on.
+    pub fn with_path(self, path: &Path) -> ImageError {
+        match self {
+            ImageError::File { .. } => self,
+            error => ImageError::File { path: path.to_path_buf(), error: Box::new(error) }
+        }
+    }
+
+    /// Path of the file the error occurred on, if known.
+    pub fn path(&self) -> Option<&Path> {
+        match *self {
+            ImageError::File { ref path, .. } => Some(path),
+            _ => None
+        }
+    }
+}
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ImageError::InvalidImage => write!(f, "invalid image"),
+            ImageError::OutOfRegion => write!(f, "region out of the image"),
+            ImageError::OutOfMemoryError => write!(f, "out of memory"),
+            ImageError::UnknownImageFormat => write!(f, "unknown image format"),
+            ImageError::UnknownError => write!(f, "unknown error"),
+            ImageError::Io(ref e) => write!(f, "{}", e),
+            ImageError::Codec { ref format, message: Some(ref message) } => {
+                write!(f, "{} codec failed: {}", format, message)
+            },
+            ImageError::Codec { ref format, message: None } => write!(f, "{} codec failed", format),
+            ImageError::UnsupportedBitDepth(bpp) => {
+                write!(f, "unsupported bit depth of {} bits per pixel", bpp)
+            },
+            ImageError::InvalidOption(ref option) => write!(f, "invalid save option: {}", option),
+            ImageError::File { ref path, ref error } => write!(f, "{}: {}", path.display(), error)
+        }
+    }
+}
+
+impl Error for ImageError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            ImageError::Io(ref e) => Some(e),
+            ImageError::File { ref error, .. } => Some(&**error),
+            _ => None
+        }
+    }
+}
+
+impl From<io::Error> for ImageError {
+    fn from(e: io::Error) -> ImageError {
+        ImageError::Io(e)
+    }
 }
 
 #[derive(PartialEq, Eq, Clone, Debug, Copy, Hash)]
@@ -51,7 +133,7 @@ impl<T: Primitive> Color<T> {
 pub const MAX_CHANNEL_COUNT: usize = 4;
 
 /// A pixel object is usually not used standalone but as a view into an image buffer.
-pub trait Pixel: Copy + Clone + Index<usize> {
+pub trait Pixel: Copy + Clone + Send + Sync + Index<usize> {
     /// The underlying subpixel type.
     type Subpixel: Primitive;
 
@@ -266,6 +348,12 @@ define_colors! {
     Gray, 1, "Y", #[doc = "Grayscale colors"];
     Bgra, 4, "BGRA", #[doc = "BGR colors + alpha channel"];
     Rgba, 4, "RGBA", #[doc = "RGB colors + alpha channel"];
+    Hsv, 3, "HSV", #[doc = "Hue, saturation, value"];
+    Hls, 3, "HLS", #[doc = "Hue, lightness, saturation"];
+    YCrCb, 3, "YCrCb", #[doc = "Luma and chroma (ITU-R BT.601)"];
+    Xyz, 3, "XYZ", #[doc = "CIE 1931 XYZ"];
+    Lab, 3, "Lab", #[doc = "CIE L*a*b*"];
+    Luv, 3, "Luv", #[doc = "CIE L*u*v*"];
 }
 
 pub trait AlphaPixel: Pixel {
@@ -336,8 +424,155 @@ define_saturating!(Gray);
 define_saturating!(Bgr);
 define_saturating!(Bgra);
 
-pub trait GenericImage {
+/// Read access shared by owning images and borrowed views, so that
+/// processing routines can accept either.
+pub trait GenericImage: MaybeSync {
     type Pixel: Pixel;
+
+    fn width(&self) -> u32;
+    fn height(&self) -> u32;
+
+    /// Returns row `r`, at least `width()` pixels long.
+    fn row(&self, r: u32) -> &[Self::Pixel];
+
+    fn size(&self) -> (u32, u32) {
+        (self.width(), self.height())
+    }
+
+    /// Copies the pixels into a newly allocated, tightly packed image.
+    fn to_image(&self) -> Image<Self::Pixel> {
+        let mut dst = Image::new(self.width(), self.height());
+        for y in 0..self.height() {
+            let w = self.width() as usize;
+            for (a, b) in dst.row_mut(y).iter_mut().zip(self.row(y)[..w].iter()) {
+                *a = *b;
+            }
+        }
+        dst
+    }
+
+    /// Converts every pixel to `D`, e.g. `image.convert::<Bgr<f32>>()`.
+    fn convert<D>(&self) -> Image<D>
+        where D: Pixel + FromPixel<Self::Pixel>
+    {
+        let mut dst = Image::new(self.width(), self.height());
+        let w = self.width() as usize;
+        for_each_row_mut(&mut dst, |y, pdst| {
+            for (d, s) in pdst[..w].iter_mut().zip(self.row(y)[..w].iter()) {
+                *d = D::from_pixel(s);
+            }
+        });
+        dst
+    }
+}
+
+// returns the offset of the top-left pixel and the length of the slice
+// covering `rect` in a buffer with the given stride
+fn roi_range(w: u32, h: u32, stride: u32, rect: &Recti) -> (usize, usize) {
+    let (x, y, rw, rh) = (rect.x, rect.y, rect.width, rect.height);
+    assert!(x >= 0 && y >= 0 && rw >= 0 && rh >= 0,
+            "invalid region {}", rect);
+    assert!(x + rw <= w as i32 && y + rh <= h as i32,
+            "region {} out of image bounds {}x{}", rect, w, h);
+    let start = y as usize * stride as usize + x as usize;
+    let len = if rh == 0 {
+        0
+    } else {
+        (rh as usize - 1) * stride as usize + rw as usize
+    };
+    (start, len)
+}
+
+// pixel storage, a `Vec` or a block allocated with a larger alignment
+// than the one of `T`, which a `Vec<T>` can not own
+enum Buffer<T: Copy> {
+    Vec(Vec<T>),
+    Aligned { ptr: *mut T, len: usize, layout: Layout }
+}
+
+unsafe impl<T: Copy + Send> Send for Buffer<T> {}
+unsafe impl<T: Copy + Sync> Sync for Buffer<T> {}
+
+impl<T: Copy> Buffer<T> {
+    // `len` copies of `v` starting on an `align` byte boundary
+    fn aligned(len: usize, align: usize, v: T) -> Buffer<T> {
+        let size = len * mem::size_of::<T>();
+        if size == 0 {
+            return Buffer::Vec(vec![v; len]);
+        }
+        let layout = Layout::from_size_align(size, align.max(mem::align_of::<T>())).unwrap();
+        unsafe {
+            let ptr = alloc::alloc(layout) as *mut T;
+            if ptr.is_null() {
+                alloc::handle_alloc_error(layout);
+            }
+            for i in 0..len {
+                ptr::write(ptr.add(i), v);
+            }
+            Buffer::Aligned { ptr: ptr, len: len, layout: layout }
+        }
+    }
+
+    fn clear(&mut self) {
+        match *self {
+            Buffer::Vec(ref mut v) => v.clear(),
+            Buffer::Aligned { ref mut len, .. } => *len = 0
+        }
+    }
+
+    fn into_vec(mut self) -> Vec<T> {
+        match self {
+            Buffer::Vec(ref mut v) => mem::replace(v, Vec::new()),
+            _ => self.to_vec()
+        }
+    }
+}
+
+impl<T: Copy> Deref for Buffer<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        match *self {
+            Buffer::Vec(ref v) => v,
+            Buffer::Aligned { ptr, len, .. } => unsafe { slice::from_raw_parts(ptr, len) }
+        }
+    }
+}
+
+impl<T: Copy> DerefMut for Buffer<T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        match *self {
+            Buffer::Vec(ref mut v) => v,
+            Buffer::Aligned { ptr, len, .. } => unsafe { slice::from_raw_parts_mut(ptr, len) }
+        }
+    }
+}
+
+impl<T: Copy> Clone for Buffer<T> {
+    fn clone(&self) -> Buffer<T> {
+        match *self {
+            Buffer::Vec(ref v) => Buffer::Vec(v.clone()),
+            Buffer::Aligned { len, layout, .. } => {
+                let mut dst = Buffer::aligned(len, layout.align(), self[0]);
+                dst.copy_from_slice(self);
+                dst
+            }
+        }
+    }
+}
+
+impl<T: Copy> Drop for Buffer<T> {
+    fn drop(&mut self) {
+        if let Buffer::Aligned { ptr, layout, .. } = *self {
+            unsafe { alloc::dealloc(ptr as *mut u8, layout); }
+        }
+    }
+}
+
+impl<T: Copy + fmt::Debug> fmt::Debug for Buffer<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
 }
 
 #[derive(Debug)]
@@ -345,27 +580,131 @@ pub struct Image<T: Pixel> {
     w: u32,
     h: u32,
     stride: u32, //stride in sizeof(T)
-    data: Vec<T>
+    align: usize, //row alignment in bytes
+    data: Buffer<T>
 }
 
 impl<T: Pixel> GenericImage for Image<T> {
     type Pixel = T;
+
+    #[inline]
+    fn width(&self) -> u32 { self.w }
+
+    #[inline]
+    fn height(&self) -> u32 { self.h }
+
+    #[inline]
+    fn row(&self, r: u32) -> &[T] {
+        Image::row(self, r)
+    }
 }
 
 impl<T: Pixel> Image<T> {
+    /// Allocates a zero-filled image.
     pub fn new(width: u32, height: u32) -> Image<T> {
-        // fast allocation without initization
+        Image::from_pixel(width, height, T::zero())
+    }
+
+    /// Allocates an image with every pixel set to `p`.
+    pub fn from_pixel(width: u32, height: u32, p: T) -> Image<T> {
+        let len = (width as usize) * (height as usize);
+        Image {
+            w: width,
+            h: height,
+            stride: width,
+            align: 1,
+            data: Buffer::Vec(vec![p; len])
+        }
+    }
+
+    /// Allocates an image with pixel `(x, y)` set to `f(x, y)`.
+    pub fn from_fn<F>(width: u32, height: u32, mut f: F) -> Image<T>
+        where F: FnMut(u32, u32) -> T
+    {
         let len = (width as usize) * (height as usize);
-        let mut data: Vec<T> = Vec::with_capacity(len);
-        unsafe { data.set_len(len); }
+        let mut data = Vec::with_capacity(len);
+        for y in 0..height {
+            for x in 0..width {
+                data.push(f(x, y));
+            }
+        }
         Image {
             w: width,
             h: height,
             stride: width,
-            data: data
+            align: 1,
+            data: Buffer::Vec(data)
+        }
+    }
+
+    /// Wraps a buffer of `stride * height` pixels, where `stride` is the
+    /// distance between rows in pixels.
+    pub fn from_vec(width: u32, height: u32, stride: u32, data: Vec<T>)
+        -> Result<Image<T>, ImageError> {
+        if stride < width
+            || data.len() < (stride as usize) * (height as usize) {
+            return Err(ImageError::InvalidImage);
+        }
+        Ok(Image {
+            w: width,
+            h: height,
+            stride: stride,
+            align: 1,
+            data: Buffer::Vec(data)
+        })
+    }
+
+    /// Builds an image from interleaved subpixels with rows `pitch` bytes
+    /// apart. The pitch must be a multiple of the pixel size.
+    pub fn from_raw_subpixels(width: u32, height: u32, pitch: u32, data: Vec<T::Subpixel>)
+        -> Result<Image<T>, ImageError> {
+        let pixel_size = mem::size_of::<T>();
+        if pitch as usize % pixel_size != 0 {
+            return Err(ImageError::InvalidImage);
         }
+        let stride = pitch / pixel_size as u32;
+        let len = (stride as usize) * (height as usize);
+        if stride < width || data.len() < len * T::channels() {
+            return Err(ImageError::InvalidImage);
+        }
+        let pixels = data.chunks(T::channels()).take(len).map(T::from_raw).collect();
+        Image::from_vec(width, height, stride, pixels)
+    }
+
+    /// Consumes the image and returns its pixel buffer, row padding
+    /// included.
+    pub fn into_raw(mut self) -> Vec<T> {
+        let mut data = mem::replace(&mut self.data, Buffer::Vec(Vec::new())).into_vec();
+        data.truncate((self.stride as usize) * (self.h as usize));
+        data
     }
 
+    /// Allocates a zero-filled image whose rows all start on an `align`
+    /// byte boundary, `align` must be a power of two. The stride is
+    /// rounded up accordingly.
+    pub fn new_aligned(width: u32, height: u32, align: usize) -> Image<T> {
+        assert!(align.is_power_of_two());
+        let pixel_size = mem::size_of::<T>();
+        // smallest pixel count spanning a multiple of `align` bytes
+        let mut unit = 1;
+        while (unit * pixel_size) % align != 0 {
+            unit += 1;
+        }
+        let stride = ((width as usize + unit - 1) / unit * unit) as u32;
+        let len = (stride as usize) * (height as usize);
+        Image {
+            w: width,
+            h: height,
+            stride: stride,
+            align: align,
+            data: Buffer::aligned(len, align, T::zero())
+        }
+    }
+
+    /// Returns the row alignment in bytes.
+    #[inline]
+    pub fn align(&self) -> usize { self.align }
+
     #[inline]
     pub fn width(&self) -> u32 { self.w }
 
@@ -379,26 +718,34 @@ impl<T: Pixel> Image<T> {
     pub fn stride(&self) -> u32 {self.stride }
 
     #[inline]
-    pub fn pitch(&self) -> u32 { self.stride * (self.bits_per_pixel() / 8) as u32 }
+    pub fn pitch(&self) -> u32 { self.bytes_per_row() as u32 }
 
     #[inline]
     pub fn pixels(&self) -> &[T] {
-        &self.data
+        let len = (self.stride as usize) * (self.h as usize);
+        &self.data[..len]
     }
 
     #[inline]
     pub fn pixels_mut(&mut self) -> &mut [T] {
-        &mut self.data
+        let len = (self.stride as usize) * (self.h as usize);
+        &mut self.data[..len]
     }
 
+    /// Returns the subpixels of all rows, including the row padding.
     pub fn raw(&self) -> &[T::Subpixel] {
-        let raw_len = self.bytes_per_row() * self.h as usize;
-        unsafe { slice::from_raw_parts(self.data.as_ptr() as *const T::Subpixel, raw_len) }
+        let raw_len = self.raw_len();
+        unsafe { slice::from_raw_parts(self.pixels().as_ptr() as *const T::Subpixel, raw_len) }
     }
 
     pub fn raw_mut(&mut self) -> &mut [T::Subpixel] {
-        let raw_len = self.bytes_per_row() * self.h as usize;
-        unsafe { slice::from_raw_parts_mut(self.data.as_mut_ptr() as *mut T::Subpixel, raw_len) }
+        let raw_len = self.raw_len();
+        unsafe { slice::from_raw_parts_mut(self.pixels_mut().as_mut_ptr() as *mut T::Subpixel, raw_len) }
+    }
+
+    #[inline]
+    fn raw_len(&self) -> usize {
+        (self.stride as usize) * (self.h as usize) * T::channels()
     }
 
     #[inline]
@@ -413,30 +760,30 @@ impl<T: Pixel> Image<T> {
 
     #[inline]
     pub fn bytes_per_row(&self) -> usize {
-        (self.stride as usize) * (T::bits_per_pixel() / 8) as usize
+        (self.stride as usize) * mem::size_of::<T>()
     }
 
     #[inline]
     pub fn row(&self, r: u32) -> &[T] {
-        let start = r * self.stride;
-        &self.data[start as usize .. (start + self.stride) as usize]
+        let start = (r * self.stride) as usize;
+        &self.data[start .. start + self.stride as usize]
     }
 
     #[inline]
     pub fn row_mut(&mut self, r: u32) -> &mut [T] {
-        let start = r * self.stride;
-        &mut self.data[start as usize .. (start + self.stride) as usize]
+        let start = (r * self.stride) as usize;
+        &mut self.data[start .. start + self.stride as usize]
     }
 
     pub fn fill(&mut self, v: &T) {
-        for p in self.data.iter_mut() {
+        for p in self.pixels_mut().iter_mut() {
             *p = *v;
         }
     }
 
     pub fn fill_channel(&mut self, ch_idx: usize, v: T::Subpixel) {
         assert!(ch_idx < T::channels() as usize);
-        for p in self.data.iter_mut() {
+        for p in self.pixels_mut().iter_mut() {
             p.raw_mut()[ch_idx] = v;
         }
     }
@@ -446,23 +793,109 @@ impl<T: Pixel> Image<T> {
     }
 
     pub fn iter(&self) -> ImageIterator<T> {
-        ImageIterator {
-            image: &self,
-            row: self.row(0),
-            y: 0,
-            x: 0
-        }
+        ImageIterator::new(self.pixels(), self.w, self.h, self.stride)
     }
 
     pub fn iter_mut(&mut self) -> ImageMutIterator<T> {
-        ImageMutIterator {
-            image: self,
-            y: 0,
-            x: 0
+        let (w, h, stride) = (self.w, self.h, self.stride);
+        ImageMutIterator::new(self.pixels_mut(), w, h, stride)
+    }
+
+    /// Returns a parallel iterator over the rows, each `stride()` pixels
+    /// long. Use `enumerate()` to get the row index. An image without
+    /// columns has no rows to iterate.
+    #[cfg(feature = "parallel")]
+    pub fn par_rows_mut(&mut self) -> ChunksMut<T> {
+        // chunks must not be empty, the pixels of such an image are
+        let stride = (self.stride as usize).max(1);
+        self.pixels_mut().par_chunks_mut(stride)
+    }
+
+    /// Borrows the region `rect` without copying.
+    ///
+    /// Panics if `rect` is not fully inside the image.
+    pub fn view(&self, rect: &Recti) -> ImageView<T> {
+        let (start, len) = roi_range(self.w, self.h, self.stride, rect);
+        ImageView {
+            w: rect.width as u32,
+            h: rect.height as u32,
+            stride: self.stride,
+            data: &self.pixels()[start .. start + len]
+        }
+    }
+
+    /// Mutably borrows the region `rect` without copying.
+    ///
+    /// Panics if `rect` is not fully inside the image.
+    pub fn view_mut(&mut self, rect: &Recti) -> ImageViewMut<T> {
+        let (start, len) = roi_range(self.w, self.h, self.stride, rect);
+        let stride = self.stride;
+        ImageViewMut {
+            w: rect.width as u32,
+            h: rect.height as u32,
+            stride: stride,
+            data: &mut self.pixels_mut()[start .. start + len]
         }
     }
-    //pub fn crop(&self, rect: &Rect) -> Result<Image<T>, ImageError> {
-    //}
+
+    /// Copies the region `rect` into a new image. The region is clipped
+    /// against the image bounds.
+    pub fn crop(&self, rect: &Recti) -> Result<Image<T>, ImageError> {
+        let r = rect.intersect(&bounds(self));
+        if r.area() <= 0 {
+            return Err(ImageError::OutOfRegion);
+        }
+        Ok(self.view(&r).to_image())
+    }
+
+    /// Pastes `src` with its top-left corner at `dst_origin`, clipping
+    /// whatever falls outside of this image.
+    pub fn copy_from<I>(&mut self, src: &I, dst_origin: &Pointi) -> Result<(), ImageError>
+        where I: GenericImage<Pixel=T>
+    {
+        let r = bounds(src);
+        self.copy_region(src, &r, dst_origin)
+    }
+
+    /// Pastes this image into `dst` at `dst_origin`, see `copy_from`.
+    pub fn copy_to(&self, dst: &mut Image<T>, dst_origin: &Pointi) -> Result<(), ImageError> {
+        dst.copy_from(self, dst_origin)
+    }
+
+    /// Copies `src_rect` of `src` to `dst_origin` of this image. Both the
+    /// source and the destination regions are clipped, `OutOfRegion` is
+    /// returned if nothing is left to copy.
+    pub fn copy_region<I>(&mut self, src: &I, src_rect: &Recti, dst_origin: &Pointi)
+        -> Result<(), ImageError>
+        where I: GenericImage<Pixel=T>
+    {
+        let sr = src_rect.intersect(&bounds(src));
+        if sr.area() <= 0 {
+            return Err(ImageError::OutOfRegion);
+        }
+        // clipping the source moves the destination along
+        let ox = dst_origin.x + sr.x - src_rect.x;
+        let oy = dst_origin.y + sr.y - src_rect.y;
+        let dr = Recti::new(ox, oy, sr.width, sr.height).intersect(&bounds(self));
+        if dr.area() <= 0 {
+            return Err(ImageError::OutOfRegion);
+        }
+        let sx = (sr.x + dr.x - ox) as usize;
+        let sy = sr.y + dr.y - oy;
+        let (dx, w) = (dr.x as usize, dr.width as usize);
+        for y in 0..dr.height {
+            let psrc = &src.row((sy + y) as u32)[sx .. sx + w];
+            let pdst = &mut self.row_mut((dr.y + y) as u32)[dx .. dx + w];
+            for (a, b) in pdst.iter_mut().zip(psrc.iter()) {
+                *a = *b;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn bounds<I: GenericImage>(image: &I) -> Recti {
+    Recti::new(0, 0, image.width() as i32, image.height() as i32)
 }
 
 pub trait AlphaImage {
@@ -488,6 +921,7 @@ impl<T: Pixel> Clone for Image<T> {
             w: self.w,
             h: self.h,
             stride: self.stride,
+            align: self.align,
             data: self.data.clone(),
         }
     }
@@ -499,21 +933,195 @@ impl<T: Pixel> Index<(u32, u32)> for Image<T> {
     #[inline]
     fn index(&self, _index: (u32, u32)) -> &T {
         let (x, y) = _index;
+        let off = (self.stride * y + x) as usize;
+        &self.data[off]
+    }
+}
+
+impl<T: Pixel> IndexMut<(u32, u32)> for Image<T> {
+    #[inline]
+    fn index_mut(&mut self, _index: (u32, u32)) -> &mut T {
+        let (x, y) = _index;
+        let off = (self.stride * y + x) as usize;
+        &mut self.data[off]
+    }
+}
+
+
+/// A borrowed region of interest of an `Image`, sharing its buffer.
+#[derive(Debug, Clone, Copy)]
+pub struct ImageView<'a, T: Pixel + 'a> {
+    w: u32,
+    h: u32,
+    stride: u32, //stride of the parent image
+    data: &'a [T]
+}
+
+impl<'a, T: Pixel + 'a> ImageView<'a, T> {
+    #[inline]
+    pub fn width(&self) -> u32 { self.w }
+
+    #[inline]
+    pub fn height(&self) -> u32 { self.h }
+
+    #[inline]
+    pub fn size(&self) -> (u32, u32) { (self.w, self.h) }
+
+    #[inline]
+    pub fn stride(&self) -> u32 { self.stride }
+
+    #[inline]
+    pub fn row(&self, r: u32) -> &'a [T] {
+        assert!(r < self.h);
+        let start = (r * self.stride) as usize;
+        let data = self.data;
+        &data[start .. start + self.w as usize]
+    }
+
+    pub fn iter(&self) -> ImageIterator<'a, T> {
+        ImageIterator::new(self.data, self.w, self.h, self.stride)
+    }
+
+    /// Borrows a sub-region, `rect` is relative to this view.
+    pub fn view(&self, rect: &Recti) -> ImageView<'a, T> {
+        let (start, len) = roi_range(self.w, self.h, self.stride, rect);
+        let data = self.data;
+        ImageView {
+            w: rect.width as u32,
+            h: rect.height as u32,
+            stride: self.stride,
+            data: &data[start .. start + len]
+        }
+    }
+}
+
+impl<'a, T: Pixel + 'a> GenericImage for ImageView<'a, T> {
+    type Pixel = T;
+
+    #[inline]
+    fn width(&self) -> u32 { self.w }
+
+    #[inline]
+    fn height(&self) -> u32 { self.h }
+
+    #[inline]
+    fn row(&self, r: u32) -> &[T] {
+        ImageView::row(self, r)
+    }
+}
+
+impl<'a, T: Pixel + 'a> Index<(u32, u32)> for ImageView<'a, T> {
+    type Output = T;
+
+    #[inline]
+    fn index(&self, _index: (u32, u32)) -> &T {
+        let (x, y) = _index;
+        assert!(x < self.w);
         let off = self.stride * y + x;
         &self.data[off as usize]
     }
 }
 
-impl<T: Pixel> IndexMut<(u32, u32)> for Image<T> {
+/// A mutably borrowed region of interest of an `Image`.
+#[derive(Debug)]
+pub struct ImageViewMut<'a, T: Pixel + 'a> {
+    w: u32,
+    h: u32,
+    stride: u32, //stride of the parent image
+    data: &'a mut [T]
+}
+
+impl<'a, T: Pixel + 'a> ImageViewMut<'a, T> {
+    #[inline]
+    pub fn width(&self) -> u32 { self.w }
+
+    #[inline]
+    pub fn height(&self) -> u32 { self.h }
+
+    #[inline]
+    pub fn size(&self) -> (u32, u32) { (self.w, self.h) }
+
+    #[inline]
+    pub fn stride(&self) -> u32 { self.stride }
+
+    #[inline]
+    pub fn row(&self, r: u32) -> &[T] {
+        assert!(r < self.h);
+        let start = (r * self.stride) as usize;
+        &self.data[start .. start + self.w as usize]
+    }
+
+    #[inline]
+    pub fn row_mut(&mut self, r: u32) -> &mut [T] {
+        assert!(r < self.h);
+        let start = (r * self.stride) as usize;
+        &mut self.data[start .. start + self.w as usize]
+    }
+
+    pub fn fill(&mut self, v: &T) {
+        for y in 0..self.h {
+            for p in self.row_mut(y).iter_mut() {
+                *p = *v;
+            }
+        }
+    }
+
+    pub fn iter(&self) -> ImageIterator<T> {
+        ImageIterator::new(self.data, self.w, self.h, self.stride)
+    }
+
+    pub fn iter_mut(&mut self) -> ImageMutIterator<T> {
+        ImageMutIterator::new(self.data, self.w, self.h, self.stride)
+    }
+
+    /// Reborrows the whole region as an immutable view.
+    pub fn as_view(&self) -> ImageView<T> {
+        ImageView {
+            w: self.w,
+            h: self.h,
+            stride: self.stride,
+            data: self.data
+        }
+    }
+}
+
+impl<'a, T: Pixel + 'a> GenericImage for ImageViewMut<'a, T> {
+    type Pixel = T;
+
+    #[inline]
+    fn width(&self) -> u32 { self.w }
+
+    #[inline]
+    fn height(&self) -> u32 { self.h }
+
+    #[inline]
+    fn row(&self, r: u32) -> &[T] {
+        ImageViewMut::row(self, r)
+    }
+}
+
+impl<'a, T: Pixel + 'a> Index<(u32, u32)> for ImageViewMut<'a, T> {
+    type Output = T;
+
+    #[inline]
+    fn index(&self, _index: (u32, u32)) -> &T {
+        let (x, y) = _index;
+        assert!(x < self.w);
+        let off = self.stride * y + x;
+        &self.data[off as usize]
+    }
+}
+
+impl<'a, T: Pixel + 'a> IndexMut<(u32, u32)> for ImageViewMut<'a, T> {
     #[inline]
     fn index_mut(&mut self, _index: (u32, u32)) -> &mut T {
         let (x, y) = _index;
+        assert!(x < self.w);
         let off = self.stride * y + x;
         &mut self.data[off as usize]
     }
 }
 
-
 pub type ImageGray = Image<Gray<u8>>;
 pub type ImageBgr = Image<Bgr<u8>>;
 pub type ImageBgra = Image<Bgra<u8>>;
@@ -527,12 +1135,31 @@ pub struct ImageIterator<'a, P>
           <P as Index<usize>>::Output: 'a,
           P::Subpixel: 'a
 {
-    image: &'a Image<P>,
-    row: &'a [P],
+    data: &'a [P],
+    width: u32,
+    height: u32,
+    stride: u32,
     y: u32,
     x: u32
 }
 
+impl<'a, P> ImageIterator<'a, P>
+    where P: Pixel + 'a,
+          <P as Index<usize>>::Output: 'a,
+          P::Subpixel: 'a,
+{
+    fn new(data: &'a [P], width: u32, height: u32, stride: u32) -> ImageIterator<'a, P> {
+        ImageIterator {
+            data: data,
+            width: width,
+            height: height,
+            stride: stride,
+            y: 0,
+            x: 0
+        }
+    }
+}
+
 impl<'a, P> Iterator for ImageIterator<'a, P> 
     where P: Pixel + 'a,
           <P as Index<usize>>::Output: 'a,
@@ -541,18 +1168,20 @@ impl<'a, P> Iterator for ImageIterator<'a, P>
     type Item = (u32, u32, &'a P);
     #[inline]
     fn next(&mut self) -> Option<(u32, u32, &'a P)> {
-        if self.x >= self.image.width() {
+        if self.width == 0 {
+            return None;
+        }
+        if self.x >= self.width {
             self.y += 1;
-            if self.y >= self.image.height() {
-                return None;
-            } else {
-                self.row = self.image.row(self.y);
-            }
             self.x  = 0;
         }
+        if self.y >= self.height {
+            return None;
+        }
         let (x, y) = (self.x, self.y);
         self.x += 1;
-        Some((x, y, &self.row[x as usize]))
+        let data = self.data;
+        Some((x, y, &data[(y * self.stride + x) as usize]))
     }
 }
 
@@ -561,11 +1190,31 @@ pub struct ImageMutIterator<'a, P>
           <P as Index<usize>>::Output: 'a,
           P::Subpixel: 'a
 {
-    image: &'a mut Image<P>,
+    data: &'a mut [P],
+    width: u32,
+    height: u32,
+    stride: u32,
     y: u32,
     x: u32
 }
 
+impl<'a, P> ImageMutIterator<'a, P>
+    where P: Pixel + 'a,
+          <P as Index<usize>>::Output: 'a,
+          P::Subpixel: 'a,
+{
+    fn new(data: &'a mut [P], width: u32, height: u32, stride: u32) -> ImageMutIterator<'a, P> {
+        ImageMutIterator {
+            data: data,
+            width: width,
+            height: height,
+            stride: stride,
+            y: 0,
+            x: 0
+        }
+    }
+}
+
 impl<'a, P> Iterator for ImageMutIterator<'a, P> 
     where P: Pixel + 'a,
           <P as Index<usize>>::Output: 'a,
@@ -574,18 +1223,21 @@ impl<'a, P> Iterator for ImageMutIterator<'a, P>
     type Item = (u32, u32, &'a mut P);
     #[inline]
     fn next(&mut self) -> Option<(u32, u32, &'a mut P)> {
-        if self.x >= self.image.width() {
+        if self.width == 0 {
+            return None;
+        }
+        if self.x >= self.width {
             self.y += 1;
             self.x  = 0;
         }
-        if self.y >= self.image.height() {
+        if self.y >= self.height {
             return None;
         }
         let (x, y) = (self.x, self.y);
         self.x += 1;
         // TODO: implement this without `unsafe'
         unsafe {
-            let t: *mut P = &mut self.image.row_mut(y)[x as usize];
+            let t: *mut P = &mut self.data[(y * self.stride + x) as usize];
             Some((x, y, &mut *t))
         }
     }
@@ -597,6 +1249,7 @@ impl<'a, P> Iterator for ImageMutIterator<'a, P>
 mod test {
     use std::mem;
     use super::*;
+    use geo::{Pointi, Recti};
 
     #[test]
     fn test_pixel_size() {
@@ -615,6 +1268,109 @@ mod test {
         assert_eq!(img.pitch(), 100 * 4);
     }
 
+    #[test]
+    fn test_alloc_init() {
+        let img = ImageBgr::new(3, 2);
+        assert!(img.pixels().iter().all(|p| *p == Bgr([0, 0, 0])));
+
+        let img = ImageBgr::from_pixel(3, 2, Bgr([1, 2, 3]));
+        assert_eq!(img.pixels().len(), 6);
+        assert!(img.pixels().iter().all(|p| *p == Bgr([1, 2, 3])));
+
+        let img = ImageGray::from_fn(3, 2, |x, y| Gray([(y * 3 + x) as u8]));
+        assert_eq!(img.pixels(), &[Gray([0]), Gray([1]), Gray([2]),
+                                   Gray([3]), Gray([4]), Gray([5])]);
+    }
+
+    #[test]
+    fn test_from_vec() {
+        let data: Vec<Gray<u8>> = (0..12).map(|i| Gray([i as u8])).collect();
+        let img = ImageGray::from_vec(3, 3, 4, data).unwrap();
+        assert_eq!(img.stride(), 4);
+        assert_eq!(img.pitch(), 4);
+        assert_eq!(img.raw().len(), 12);
+        assert_eq!(img[(2, 1)], Gray([6]));
+        assert_eq!(img.iter().map(|(_, _, p)| p[0]).collect::<Vec<u8>>(),
+                   vec![0, 1, 2, 4, 5, 6, 8, 9, 10]);
+        assert_eq!(img.into_raw().len(), 12);
+
+        assert!(ImageGray::from_vec(3, 3, 2, vec![Gray([0]); 12]).is_err());
+        assert!(ImageGray::from_vec(3, 3, 4, vec![Gray([0]); 11]).is_err());
+    }
+
+    #[test]
+    fn test_from_raw_subpixels() {
+        let data: Vec<f32> = (0..16).map(|i| i as f32).collect();
+        let img = Image::<Bgr<f32>>::from_raw_subpixels(1, 2, 24, data).unwrap();
+        assert_eq!(img.stride(), 2);
+        assert_eq!(img.pitch(), 24);
+        assert_eq!(img.raw().len(), 12);
+        assert_eq!(img[(0, 1)], Bgr([6.0, 7.0, 8.0]));
+
+        let data = vec![0u8; 64];
+        assert!(ImageBgr::from_raw_subpixels(10, 2, 32, data.clone()).is_err());
+        assert!(ImageBgr::from_raw_subpixels(10, 2, 30, data.clone()).is_ok());
+        assert!(ImageBgr::from_raw_subpixels(10, 3, 30, data).is_err());
+    }
+
+    #[test]
+    fn test_aligned() {
+        for &align in [16usize, 32, 64].iter() {
+            let mut img = ImageBgr::new_aligned(10, 5, align);
+            assert_eq!(img.align(), align);
+            assert!(img.stride() >= 10);
+            assert_eq!(img.pitch() as usize % align, 0);
+            for y in 0..5 {
+                assert_eq!(img.row(y).as_ptr() as usize % align, 0);
+            }
+            img[(9, 4)] = Bgr([1, 2, 3]);
+            let copy = img.clone();
+            assert_eq!(copy.row(0).as_ptr() as usize % align, 0);
+            assert_eq!(copy[(9, 4)], Bgr([1, 2, 3]));
+            let stride = img.stride() as usize;
+            let raw = img.into_raw();
+            assert_eq!(raw.len(), stride * 5);
+            assert_eq!(raw[stride * 4 + 9], Bgr([1, 2, 3]));
+        }
+        let img = ImageGray::new_aligned(10, 2, 16);
+        assert_eq!(img.stride(), 16);
+        // byte aligned pixels
+        for _ in 0..8 {
+            let img = ImageBgra::new_aligned(7, 3, 64);
+            assert_eq!(img.row(2).as_ptr() as usize % 64, 0);
+            assert_eq!(img.stride(), 16);
+        }
+        assert_eq!(ImageBgra::new_aligned(0, 3, 64).into_raw().len(), 0);
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn test_par_rows_mut() {
+        use rayon::prelude::*;
+        let mut img = ImageGray::new(7, 30);
+        img.par_rows_mut().enumerate().for_each(|(y, row)| {
+            for (x, p) in row.iter_mut().enumerate() {
+                *p = Gray([(y + x) as u8]);
+            }
+        });
+        for (x, y, p) in img.iter() {
+            assert_eq!(*p, Gray([(x + y) as u8]));
+        }
+        assert_eq!(ImageGray::new(0, 5).par_rows_mut().count(), 0);
+    }
+
+    #[test]
+    fn test_for_each_row_mut_empty() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        let calls = AtomicUsize::new(0);
+        for &(w, h) in [(0, 5), (5, 0)].iter() {
+            for_each_row_mut(&mut ImageGray::new(w, h), |_, _| {
+                calls.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+    }
+
     #[test]
     fn test_iter() {
         let mut img = ImageBgra::new(10, 5);
@@ -627,6 +1383,98 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_view() {
+        let mut img = ImageGray::new(6, 4);
+        for (x, y, p) in img.iter_mut() {
+            *p = Gray([(y * 10 + x) as u8]);
+        }
+        let v = img.view(&Recti::new(1, 2, 3, 2));
+        assert_eq!(v.size(), (3, 2));
+        assert_eq!(v.row(0), &[Gray([21]), Gray([22]), Gray([23])]);
+        assert_eq!(v[(2, 1)], Gray([33]));
+        let px: Vec<(u32, u32, u8)> = v.iter().map(|(x, y, p)| (x, y, p[0])).collect();
+        assert_eq!(px, vec![(0, 0, 21), (1, 0, 22), (2, 0, 23),
+                            (0, 1, 31), (1, 1, 32), (2, 1, 33)]);
+
+        let sub = v.view(&Recti::new(1, 1, 2, 1));
+        assert_eq!(sub.row(0), &[Gray([32]), Gray([33])]);
+        assert_eq!(sub.to_image().pixels(), &[Gray([32]), Gray([33])]);
+    }
+
+    #[test]
+    fn test_view_mut() {
+        let mut img = ImageGray::new(5, 5);
+        img.zero();
+        {
+            let mut v = img.view_mut(&Recti::new(1, 1, 3, 2));
+            v.fill(&Gray([1]));
+            v[(0, 1)] = Gray([2]);
+            for (_, _, p) in v.iter_mut() {
+                p[0] += 1;
+            }
+        }
+        let sum = img.iter().fold(0u32, |s, (_, _, p)| s + p[0] as u32);
+        assert_eq!(sum, 5 * 2 + 3);
+        assert_eq!(img[(1, 2)], Gray([3]));
+        assert_eq!(img[(0, 1)], Gray([0]));
+        assert_eq!(img[(4, 1)], Gray([0]));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_view_out_of_bounds() {
+        let img = ImageGray::new(5, 5);
+        let _ = img.view(&Recti::new(3, 3, 3, 1));
+    }
+
+    #[test]
+    fn test_crop() {
+        let mut img = ImageGray::new(6, 4);
+        for (x, y, p) in img.iter_mut() {
+            *p = Gray([(y * 10 + x) as u8]);
+        }
+        let out = img.crop(&Recti::new(4, 2, 5, 5)).unwrap();
+        assert_eq!(out.size(), (2, 2));
+        assert_eq!(out.pixels(), &[Gray([24]), Gray([25]), Gray([34]), Gray([35])]);
+
+        let out = img.crop(&Recti::new(-1, -1, 2, 3)).unwrap();
+        assert_eq!(out.size(), (1, 2));
+        assert_eq!(out.pixels(), &[Gray([0]), Gray([10])]);
+
+        match img.crop(&Recti::new(6, 0, 2, 2)) {
+            Err(ImageError::OutOfRegion) => (),
+            _ => panic!("expected OutOfRegion")
+        }
+    }
+
+    #[test]
+    fn test_copy() {
+        let mut src = ImageGray::new(3, 3);
+        for (x, y, p) in src.iter_mut() {
+            *p = Gray([(1 + y * 3 + x) as u8]);
+        }
+        let mut dst = ImageGray::new(4, 4);
+        dst.zero();
+        dst.copy_from(&src, &Pointi::new(-1, 2)).unwrap();
+        assert_eq!(dst.row(2), &[Gray([2]), Gray([3]), Gray([0]), Gray([0])]);
+        assert_eq!(dst.row(3), &[Gray([5]), Gray([6]), Gray([0]), Gray([0])]);
+        assert_eq!(dst.row(1), &[Gray([0]); 4]);
+
+        dst.zero();
+        dst.copy_region(&src, &Recti::new(1, -1, 5, 2), &Pointi::new(0, 0)).unwrap();
+        assert_eq!(dst.row(0), &[Gray([0]); 4]);
+        assert_eq!(dst.row(1), &[Gray([2]), Gray([3]), Gray([0]), Gray([0])]);
+
+        src.copy_to(&mut dst, &Pointi::new(2, 2)).unwrap();
+        assert_eq!(dst[(3, 3)], Gray([5]));
+
+        match dst.copy_from(&src.view(&Recti::new(0, 0, 2, 2)), &Pointi::new(4, 0)) {
+            Err(ImageError::OutOfRegion) => (),
+            _ => panic!("expected OutOfRegion")
+        }
+    }
+
     #[test]
     fn test_traits() {
         let mut img = ImageBgra::new(10, 5);
@@ -635,5 +1483,27 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_image_error() {
+        let e = ImageError::Codec { format: "PNG".to_string(),
+                                    message: Some("bad crc".to_string()) };
+        let e = e.with_path(Path::new("/data/a.png"));
+        assert_eq!(e.to_string(), "/data/a.png: PNG codec failed: bad crc");
+        assert_eq!(e.path(), Some(Path::new("/data/a.png")));
+        // the innermost path is kept
+        let e = e.with_path(Path::new("b.png"));
+        assert_eq!(e.path(), Some(Path::new("/data/a.png")));
+        assert!(e.source().unwrap().source().is_none());
+
+        let e = ImageError::from(io::Error::new(io::ErrorKind::NotFound, "no such file"));
+        assert_eq!(e.to_string(), "no such file");
+        assert!(e.source().is_some());
+        assert!(e.path().is_none());
+        assert_eq!(ImageError::UnsupportedBitDepth(48).to_string(),
+                   "unsupported bit depth of 48 bits per pixel");
+        let e = ImageError::InvalidOption("JPEG quality 0 out of 1..100".to_string());
+        assert_eq!(e.to_string(), "invalid save option: JPEG quality 0 out of 1..100");
+    }
+
 }
 
@@ -1,19 +1,25 @@
 extern crate libc;
 
-use std::ffi::CString;
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::fs::File;
+use std::io::{self, Read, Write, Seek, SeekFrom};
+use std::panic::{self, AssertUnwindSafe};
 use std::path::Path;
 use std::ptr;
-use self::libc::{c_int, c_uint, c_void, c_char, c_uchar};
+use std::slice;
+use self::libc::{c_int, c_uint, c_long, c_void, c_char, c_uchar};
 use std::sync::{Once, ONCE_INIT};
 
-use imageio::{ImageIO, ImagePing, ImageInfo};
+use imageio::{ImageIO, ImagePing, ImageInfo, ImageFormat, SaveOptions, ChromaSubsampling,
+    TiffCompression};
 use image::{ImageBgra, ImageBgr, ImageGray,
     ImageError, Image, Pixel};
 
 #[repr(C)]
 #[allow(non_camel_case_types)]
 #[derive(Debug, PartialEq, Eq, Clone)]
-enum ImageFormat {
+enum FreeImageFormat {
 	FIF_UNKNOWN = -1,
 	FIF_BMP		= 0,
 	FIF_ICO		= 1,
@@ -54,20 +60,85 @@ enum ImageFormat {
 	FIF_JXR		= 36
 }
 
+macro_rules! map_formats(
+    ($($format:ident => $fif:ident),+) => (
+        fn to_fif(format: ImageFormat) -> FreeImageFormat {
+            match format {
+                $(ImageFormat::$format => FreeImageFormat::$fif),+
+            }
+        }
+
+        fn from_fif(fif: &FreeImageFormat) -> Option<ImageFormat> {
+            match *fif {
+                $(FreeImageFormat::$fif => Some(ImageFormat::$format),)+
+                FreeImageFormat::FIF_UNKNOWN => None
+            }
+        }
+    );
+);
+
+map_formats!(Bmp => FIF_BMP, Ico => FIF_ICO, Jpeg => FIF_JPEG, Jng => FIF_JNG,
+             Koala => FIF_KOALA, Lbm => FIF_LBM, Mng => FIF_MNG, Pbm => FIF_PBM,
+             PbmRaw => FIF_PBMRAW, Pcd => FIF_PCD, Pcx => FIF_PCX, Pgm => FIF_PGM,
+             PgmRaw => FIF_PGMRAW, Png => FIF_PNG, Ppm => FIF_PPM, PpmRaw => FIF_PPMRAW,
+             Ras => FIF_RAS, Targa => FIF_TARGA, Tiff => FIF_TIFF, Wbmp => FIF_WBMP,
+             Psd => FIF_PSD, Cut => FIF_CUT, Xbm => FIF_XBM, Xpm => FIF_XPM, Dds => FIF_DDS,
+             Gif => FIF_GIF, Hdr => FIF_HDR, FaxG3 => FIF_FAXG3, Sgi => FIF_SGI,
+             Exr => FIF_EXR, J2k => FIF_J2K, Jp2 => FIF_JP2, Pfm => FIF_PFM, Pict => FIF_PICT,
+             Raw => FIF_RAW, Webp => FIF_WEBP, Jxr => FIF_JXR);
+
+// FreeImageIO of FreeImage.h, callbacks reading or writing an opaque handle
+#[repr(C)]
+struct IoCallbacks {
+    read_proc: extern "C" fn(*mut c_void, c_uint, c_uint, *mut c_void) -> c_uint,
+    write_proc: extern "C" fn(*mut c_void, c_uint, c_uint, *mut c_void) -> c_uint,
+    seek_proc: extern "C" fn(*mut c_void, c_long, c_int) -> c_int,
+    tell_proc: extern "C" fn(*mut c_void) -> c_long
+}
+
 const JPEG_EXIFROTATE: c_int = 0x0008;
 const FIF_LOAD_NOPIXELS: c_int = 0x8000;
 
+const JPEG_PROGRESSIVE: c_int = 0x2000;
+const JPEG_SUBSAMPLING_411: c_int = 0x1000;
+const JPEG_SUBSAMPLING_420: c_int = 0x4000;
+const JPEG_SUBSAMPLING_422: c_int = 0x8000;
+const JPEG_SUBSAMPLING_444: c_int = 0x10000;
+const JPEG_OPTIMIZE: c_int = 0x20000;
+const PNG_Z_NO_COMPRESSION: c_int = 0x0100;
+const TIFF_PACKBITS: c_int = 0x0100;
+const TIFF_DEFLATE: c_int = 0x0200;
+const TIFF_ADOBE_DEFLATE: c_int = 0x0400;
+const TIFF_NONE: c_int = 0x0800;
+const TIFF_LZW: c_int = 0x4000;
+const WEBP_LOSSLESS: c_int = 0x0100;
+
 #[link(name = "freeimage", kind = "static")]
 extern {
     fn FreeImage_Initialise(load_local_only: c_int);
     fn FreeImage_DeInitialise();
+    fn FreeImage_SetOutputMessage(omf: extern "C" fn(c_int, *const c_char));
     fn FreeImage_Allocate(width: c_int, height: c_int, bpp: c_int, red_mask: c_uint, green_mask: c_uint, blue_mask: c_uint) -> *mut c_void;
-    fn FreeImage_Load(fif: ImageFormat, filename: *const c_char, flag: c_int) -> *mut c_void;
-    fn FreeImage_Save(fif: ImageFormat, dib: *mut c_void, filename: *const c_char, flags: c_int) -> c_int;
+    fn FreeImage_Load(fif: FreeImageFormat, filename: *const c_char, flag: c_int) -> *mut c_void;
+    fn FreeImage_Save(fif: FreeImageFormat, dib: *mut c_void, filename: *const c_char, flags: c_int) -> c_int;
     fn FreeImage_Unload(dib: *mut c_void);
 
-    fn FreeImage_GetFileType(filename: *const c_char, size: c_int) -> ImageFormat;
-    fn FreeImage_GetFIFFromFilename(filename: *const c_char) -> ImageFormat;
+    fn FreeImage_OpenMemory(data: *mut c_uchar, size: u32) -> *mut c_void;
+    fn FreeImage_CloseMemory(stream: *mut c_void);
+    fn FreeImage_AcquireMemory(stream: *mut c_void, data: *mut *mut c_uchar, size: *mut u32) -> c_int;
+    fn FreeImage_LoadFromMemory(fif: FreeImageFormat, stream: *mut c_void, flags: c_int) -> *mut c_void;
+    fn FreeImage_SaveToMemory(fif: FreeImageFormat, dib: *mut c_void, stream: *mut c_void, flags: c_int) -> c_int;
+
+    fn FreeImage_LoadFromHandle(fif: FreeImageFormat, io: *mut IoCallbacks, handle: *mut c_void, flags: c_int) -> *mut c_void;
+    fn FreeImage_SaveToHandle(fif: FreeImageFormat, dib: *mut c_void, io: *mut IoCallbacks, handle: *mut c_void, flags: c_int) -> c_int;
+
+    fn FreeImage_GetFileType(filename: *const c_char, size: c_int) -> FreeImageFormat;
+    fn FreeImage_GetFileTypeFromMemory(stream: *mut c_void, size: c_int) -> FreeImageFormat;
+    fn FreeImage_GetFileTypeFromHandle(io: *mut IoCallbacks, handle: *mut c_void, size: c_int) -> FreeImageFormat;
+    fn FreeImage_GetFIFFromFilename(filename: *const c_char) -> FreeImageFormat;
+    fn FreeImage_GetFormatFromFIF(fif: FreeImageFormat) -> *const c_char;
+    fn FreeImage_FIFSupportsReading(fif: FreeImageFormat) -> c_int;
+    fn FreeImage_FIFSupportsWriting(fif: FreeImageFormat) -> c_int;
 
     fn FreeImage_GetWidth(dib: *mut c_void) -> u32;
     fn FreeImage_GetHeight(dib: *mut c_void) -> u32;
@@ -81,12 +152,65 @@ extern {
     fn FreeImage_Clone(dib: *mut c_void) -> *mut c_void;
 }
 
+thread_local!(static LAST_MESSAGE: RefCell<Option<String>> = RefCell::new(None));
+
+// FreeImage reports errors through this callback, on the failing thread
+extern "C" fn output_message(_fif: c_int, msg: *const c_char) {
+    if msg.is_null() {
+        return;
+    }
+    let msg = unsafe { CStr::from_ptr(msg) }.to_string_lossy().into_owned();
+    LAST_MESSAGE.with(|m| *m.borrow_mut() = Some(msg));
+}
+
+fn take_message() -> Option<String> {
+    LAST_MESSAGE.with(|m| m.borrow_mut().take())
+}
+
 fn init() {
     static LIBSTART: Once = ONCE_INIT;
     LIBSTART.call_once(|| {
         // XXX not unloaded
-        unsafe { FreeImage_Initialise(0); }
+        unsafe {
+            FreeImage_Initialise(0);
+            FreeImage_SetOutputMessage(output_message);
+        }
     });
+    // drop a message left by an earlier call
+    take_message();
+}
+
+fn format_name(format: &FreeImageFormat) -> String {
+    let name = unsafe { FreeImage_GetFormatFromFIF(format.clone()) };
+    if name.is_null() {
+        format!("{:?}", format)
+    } else {
+        unsafe { CStr::from_ptr(name) }.to_string_lossy().into_owned()
+    }
+}
+
+fn codec_error(format: &FreeImageFormat) -> ImageError {
+    ImageError::Codec { format: format_name(format), message: take_message() }
+}
+
+// FreeImage takes the bytes of the path, whatever their encoding
+#[cfg(unix)]
+fn path_to_cstring(path: &Path) -> Result<CString, ImageError> {
+    use std::os::unix::ffi::OsStrExt;
+    CString::new(path.as_os_str().as_bytes()).map_err(|_| invalid_path(path))
+}
+
+#[cfg(not(unix))]
+fn path_to_cstring(path: &Path) -> Result<CString, ImageError> {
+    match path.to_str() {
+        Some(s) => CString::new(s).map_err(|_| invalid_path(path)),
+        None => Err(invalid_path(path))
+    }
+}
+
+fn invalid_path(path: &Path) -> ImageError {
+    ImageError::Io(io::Error::new(io::ErrorKind::InvalidInput, "path not supported by FreeImage"))
+        .with_path(path)
 }
 
 unsafe fn from_raw<T: Pixel>(np: *mut c_void) -> Image<T> {
@@ -96,6 +220,7 @@ unsafe fn from_raw<T: Pixel>(np: *mut c_void) -> Image<T> {
     let mut image = Image::new(w, h);
     {
         let stride_dst = image.pitch();
+        let row_bytes = image.width() * (image.bits_per_pixel() / 8) as u32;
         let mut dst = image.raw_mut();
         let pdst = dst.as_mut_ptr() as *mut u8;
         let sptr = FreeImage_GetBits(np);
@@ -103,51 +228,234 @@ unsafe fn from_raw<T: Pixel>(np: *mut c_void) -> Image<T> {
             panic!("No image data!");
         }
 
-        let sptr_end =  sptr.offset((pitch * (h - 1)) as isize);
-        for y in 0..h {
-            // freeimage save image reversely
-            ptr::copy(sptr_end.offset(-((y * pitch) as isize)),
-            pdst.offset((y * stride_dst) as isize),
-            stride_dst as usize);
+        if h > 0 {
+            let sptr_end =  sptr.offset((pitch * (h - 1)) as isize);
+            for y in 0..h {
+                // freeimage save image reversely
+                ptr::copy(sptr_end.offset(-((y * pitch) as isize)),
+                pdst.offset((y * stride_dst) as isize),
+                row_bytes as usize);
+            }
         }
-
     }
     FreeImage_Unload(np);
     image
 }
 
-unsafe fn try_load_from_file(path: &Path, bits :u8, ping: bool)
-    -> *mut c_void {
-    let c_path = CString::new(path.to_str().unwrap()).unwrap();
-    let format = FreeImage_GetFileType(c_path.as_ptr(), 0);
-    if format == ImageFormat::FIF_UNKNOWN {
-        return ptr::null_mut();
-    }
+fn load_flags(format: &FreeImageFormat, ping: bool) -> c_int {
     let mut flags: c_int = 0;
     if ping {
         flags |= FIF_LOAD_NOPIXELS;
     }
-    if format == ImageFormat::FIF_JPEG {
+    if *format == FreeImageFormat::FIF_JPEG {
         flags |= JPEG_EXIFROTATE;
     }
-    let p = FreeImage_Load(format, c_path.as_ptr(), flags);
-    if ping {
-        return p;
-    }
+    flags
+}
+
+// converts a loaded bitmap to `bits` per pixel, `p` is consumed
+unsafe fn convert_loaded(p: *mut c_void, bits: u8) -> Result<*mut c_void, ImageError> {
     let np;
     let old_bpp = FreeImage_GetBPP(p);
     if old_bpp != bits as u32 {
-        match bits {
-            8  => np = FreeImage_ConvertToGreyscale(p),
-            24 => np = FreeImage_ConvertTo24Bits(p),
-            32 => np = FreeImage_ConvertTo32Bits(p),
-            _ => return ptr::null_mut()
-        }
+        np = match bits {
+            8  => FreeImage_ConvertToGreyscale(p),
+            24 => FreeImage_ConvertTo24Bits(p),
+            32 => FreeImage_ConvertTo32Bits(p),
+            _ => ptr::null_mut()
+        };
         FreeImage_Unload(p);
+        if np.is_null() {
+            return Err(ImageError::UnsupportedBitDepth(old_bpp));
+        }
     } else {
         np = p;
     }
-    np
+    Ok(np)
+}
+
+// detects the format, loads with the matching flags and converts
+unsafe fn try_load<D, L>(detect: D, load: L, bits: u8, ping: bool)
+    -> Result<(*mut c_void, FreeImageFormat), ImageError>
+    where D: FnOnce() -> FreeImageFormat,
+          L: FnOnce(FreeImageFormat, c_int) -> *mut c_void
+{
+    let format = detect();
+    if format == FreeImageFormat::FIF_UNKNOWN {
+        return Err(ImageError::UnknownImageFormat);
+    }
+    let flags = load_flags(&format, ping);
+    let p = load(format.clone(), flags);
+    if p.is_null() {
+        return Err(codec_error(&format));
+    }
+    if ping {
+        return Ok((p, format));
+    }
+    convert_loaded(p, bits).map(|p| (p, format))
+}
+
+unsafe fn try_load_from_file(path: &Path, bits :u8, ping: bool)
+    -> Result<(*mut c_void, FreeImageFormat), ImageError> {
+    let c_path = match path_to_cstring(path) {
+        Ok(c_path) => c_path,
+        Err(e) => return Err(e)
+    };
+    // FreeImage reports missing or unreadable files as unknown formats
+    if let Err(e) = File::open(path) {
+        return Err(ImageError::Io(e).with_path(path));
+    }
+    try_load(|| FreeImage_GetFileType(c_path.as_ptr(), 0),
+             |format, flags| FreeImage_Load(format, c_path.as_ptr(), flags),
+             bits, ping).map_err(|e| e.with_path(path))
+}
+
+// a FreeImage memory stream, closed on drop
+struct MemoryStream(*mut c_void);
+
+impl MemoryStream {
+    // wraps `data` without copying it, FreeImage only reads from it
+    unsafe fn wrap(data: &[u8]) -> MemoryStream {
+        MemoryStream(FreeImage_OpenMemory(data.as_ptr() as *mut c_uchar, data.len() as u32))
+    }
+
+    unsafe fn new() -> MemoryStream {
+        MemoryStream(FreeImage_OpenMemory(ptr::null_mut(), 0))
+    }
+
+    unsafe fn to_vec(&self) -> Option<Vec<u8>> {
+        let mut data: *mut c_uchar = ptr::null_mut();
+        let mut size: u32 = 0;
+        if FreeImage_AcquireMemory(self.0, &mut data, &mut size) == 0 || data.is_null() {
+            return None;
+        }
+        let mut v = Vec::with_capacity(size as usize);
+        v.extend_from_slice(slice::from_raw_parts(data, size as usize));
+        Some(v)
+    }
+}
+
+impl Drop for MemoryStream {
+    fn drop(&mut self) {
+        if !self.0.is_null() {
+            unsafe { FreeImage_CloseMemory(self.0); }
+        }
+    }
+}
+
+unsafe fn try_load_from_memory(data: &[u8], bits: u8, ping: bool)
+    -> Result<(*mut c_void, FreeImageFormat), ImageError> {
+    let stream = MemoryStream::wrap(data);
+    if stream.0.is_null() {
+        return Err(ImageError::OutOfMemoryError);
+    }
+    // the format is detected from the signature bytes
+    try_load(|| FreeImage_GetFileTypeFromMemory(stream.0, 0),
+             |format, flags| FreeImage_LoadFromMemory(format, stream.0, flags),
+             bits, ping)
+}
+
+// callbacks over a `Read + Seek` or `Write + Seek` stream passed as the
+// handle, errors are reported to FreeImage as short reads and writes and
+// panics are caught as they must not unwind into C
+extern "C" fn read_proc<R: Read>(buffer: *mut c_void, size: c_uint, count: c_uint,
+                                 handle: *mut c_void) -> c_uint {
+    panic::catch_unwind(AssertUnwindSafe(|| {
+        let reader = unsafe { &mut *(handle as *mut R) };
+        let len = size as usize * count as usize;
+        if len == 0 {
+            return 0;
+        }
+        let buf = unsafe { slice::from_raw_parts_mut(buffer as *mut u8, len) };
+        let mut n = 0;
+        while n < len {
+            match reader.read(&mut buf[n..]) {
+                Ok(0) => break,
+                Ok(k) => n += k,
+                Err(ref e) if e.kind() == ::std::io::ErrorKind::Interrupted => (),
+                Err(_) => break
+            }
+        }
+        (n / size as usize) as c_uint
+    })).unwrap_or(0)
+}
+
+extern "C" fn write_proc<W: Write>(buffer: *mut c_void, size: c_uint, count: c_uint,
+                                   handle: *mut c_void) -> c_uint {
+    panic::catch_unwind(AssertUnwindSafe(|| {
+        let writer = unsafe { &mut *(handle as *mut W) };
+        let len = size as usize * count as usize;
+        let buf = unsafe { slice::from_raw_parts(buffer as *const u8, len) };
+        match writer.write_all(buf) {
+            Ok(()) => count,
+            Err(_) => 0
+        }
+    })).unwrap_or(0)
+}
+
+extern "C" fn no_write_proc(_: *mut c_void, _: c_uint, _: c_uint, _: *mut c_void) -> c_uint {
+    0
+}
+
+extern "C" fn no_read_proc(_: *mut c_void, _: c_uint, _: c_uint, _: *mut c_void) -> c_uint {
+    0
+}
+
+// fseek semantics, 0 on success
+extern "C" fn seek_proc<S: Seek>(handle: *mut c_void, offset: c_long, origin: c_int) -> c_int {
+    panic::catch_unwind(AssertUnwindSafe(|| {
+        let stream = unsafe { &mut *(handle as *mut S) };
+        let pos = match origin {
+            0 if offset >= 0 => SeekFrom::Start(offset as u64),
+            1 => SeekFrom::Current(offset as i64),
+            2 => SeekFrom::End(offset as i64),
+            _ => return -1
+        };
+        match stream.seek(pos) {
+            Ok(_) => 0,
+            Err(_) => -1
+        }
+    })).unwrap_or(-1)
+}
+
+// ftell semantics, -1 on error or when the position overflows a `c_long`
+extern "C" fn tell_proc<S: Seek>(handle: *mut c_void) -> c_long {
+    panic::catch_unwind(AssertUnwindSafe(|| {
+        let stream = unsafe { &mut *(handle as *mut S) };
+        match stream.seek(SeekFrom::Current(0)) {
+            Ok(pos) if pos <= c_long::max_value() as u64 => pos as c_long,
+            _ => -1
+        }
+    })).unwrap_or(-1)
+}
+
+fn reader_callbacks<R: Read + Seek>() -> IoCallbacks {
+    IoCallbacks {
+        read_proc: read_proc::<R>,
+        write_proc: no_write_proc,
+        seek_proc: seek_proc::<R>,
+        tell_proc: tell_proc::<R>
+    }
+}
+
+fn writer_callbacks<W: Write + Seek>() -> IoCallbacks {
+    IoCallbacks {
+        read_proc: no_read_proc,
+        write_proc: write_proc::<W>,
+        seek_proc: seek_proc::<W>,
+        tell_proc: tell_proc::<W>
+    }
+}
+
+// loads from the current position of `reader`
+unsafe fn try_load_from_reader<R: Read + Seek>(reader: &mut R, bits: u8, ping: bool)
+    -> Result<(*mut c_void, FreeImageFormat), ImageError> {
+    let mut callbacks = reader_callbacks::<R>();
+    let io = &mut callbacks as *mut IoCallbacks;
+    let handle = reader as *mut R as *mut c_void;
+    try_load(|| FreeImage_GetFileTypeFromHandle(io, handle, 0),
+             |format, flags| FreeImage_LoadFromHandle(format, io, handle, flags),
+             bits, ping)
 }
 
 unsafe fn to_raw<T: Pixel>(image: &Image<T>) -> *mut c_void {
@@ -164,6 +472,7 @@ unsafe fn to_raw<T: Pixel>(image: &Image<T>) -> *mut c_void {
         let h = FreeImage_GetHeight(p);
         let pitch = FreeImage_GetPitch(p);
         let stride_src = image.pitch();
+        let row_bytes = image.width() * (image.bits_per_pixel() / 8) as u32;
         let src = image.raw();
         let psrc = src.as_ptr() as *const u8;
         let dptr = FreeImage_GetBits(p);
@@ -171,71 +480,294 @@ unsafe fn to_raw<T: Pixel>(image: &Image<T>) -> *mut c_void {
             panic!("No image data!");
         }
 
-        let dptr_end =  dptr.offset((pitch * (h - 1)) as isize);
-        for y in 0..h {
-            // freeimage save image reversely
-            ptr::copy(psrc.offset((y * stride_src) as isize),
-            dptr_end.offset(-((y * pitch) as isize)),
-            stride_src as usize);
+        if h > 0 {
+            let dptr_end =  dptr.offset((pitch * (h - 1)) as isize);
+            for y in 0..h {
+                // freeimage save image reversely
+                ptr::copy(psrc.offset((y * stride_src) as isize),
+                dptr_end.offset(-((y * pitch) as isize)),
+                row_bytes as usize);
+            }
         }
     }
     p
 }
 
-unsafe fn save_raw_to_file(p: *mut c_void, path: &Path, src_bits: u8, format: ImageFormat) -> i32 {
+// FreeImage_Save flags of `options` for `format`, options out of the range
+// of the format are an error
+fn save_flags(format: &FreeImageFormat, options: &SaveOptions) -> Result<c_int, ImageError> {
+    let mut flags: c_int = 0;
+    match *format {
+        FreeImageFormat::FIF_JPEG => {
+            if let Some(q) = options.jpeg_quality {
+                if q < 1 || q > 100 {
+                    let msg = format!("JPEG quality {} out of 1..100", q);
+                    return Err(ImageError::InvalidOption(msg));
+                }
+                flags |= q as c_int;
+            }
+            flags |= match options.jpeg_subsampling {
+                Some(ChromaSubsampling::S411) => JPEG_SUBSAMPLING_411,
+                Some(ChromaSubsampling::S420) => JPEG_SUBSAMPLING_420,
+                Some(ChromaSubsampling::S422) => JPEG_SUBSAMPLING_422,
+                Some(ChromaSubsampling::S444) => JPEG_SUBSAMPLING_444,
+                None => 0
+            };
+            if options.jpeg_progressive {
+                flags |= JPEG_PROGRESSIVE;
+            }
+            if options.jpeg_optimize {
+                flags |= JPEG_OPTIMIZE;
+            }
+        },
+        FreeImageFormat::FIF_PNG => {
+            match options.png_compression {
+                Some(0) => flags |= PNG_Z_NO_COMPRESSION,
+                Some(level) => {
+                    if level > 9 {
+                        let msg = format!("PNG compression level {} out of 0..9", level);
+                        return Err(ImageError::InvalidOption(msg));
+                    }
+                    flags |= level as c_int;
+                },
+                None => ()
+            }
+        },
+        FreeImageFormat::FIF_TIFF => {
+            flags |= match options.tiff_compression {
+                Some(TiffCompression::None) => TIFF_NONE,
+                Some(TiffCompression::PackBits) => TIFF_PACKBITS,
+                Some(TiffCompression::Lzw) => TIFF_LZW,
+                Some(TiffCompression::Deflate) => TIFF_DEFLATE,
+                Some(TiffCompression::AdobeDeflate) => TIFF_ADOBE_DEFLATE,
+                None => 0
+            };
+        },
+        FreeImageFormat::FIF_WEBP => {
+            if options.webp_lossless {
+                flags |= WEBP_LOSSLESS;
+            } else if let Some(q) = options.webp_quality {
+                if q < 1 || q > 100 {
+                    let msg = format!("WebP quality {} out of 1..100", q);
+                    return Err(ImageError::InvalidOption(msg));
+                }
+                flags |= q as c_int;
+            }
+        },
+        _ => ()
+    }
+    Ok(flags)
+}
+
+// FreeImage format of `encode_with_options` and
+// `save_to_writer_with_options`, a different `options.format` is an error
+fn stream_format(format: ImageFormat, options: &SaveOptions)
+    -> Result<FreeImageFormat, ImageError> {
+    match options.format {
+        Some(f) if f != format => {
+            let msg = format!("format {:?} differs from the encoded {:?}", f, format);
+            Err(ImageError::InvalidOption(msg))
+        },
+        _ => Ok(to_fif(format))
+    }
+}
+
+// hands `p`, or its 24 bits conversion for 32 bits JPEG, to `save` and
+// unloads it
+unsafe fn save_raw<F>(p: *mut c_void, src_bits: u8, format: &FreeImageFormat, save: F)
+    -> Result<(), ImageError>
+    where F: FnOnce(*mut c_void) -> c_int
+{
     let code;
-    let c_path = CString::new(path.to_str().unwrap()).unwrap();
     assert!(src_bits == 8 || src_bits == 24 || src_bits == 32);
-    if format != ImageFormat::FIF_JPEG || src_bits != 32 {
-        code = FreeImage_Save(format, p, c_path.as_ptr(), 0);
+    if *format != FreeImageFormat::FIF_JPEG || src_bits != 32 {
+        code = save(p);
         FreeImage_Unload(p);
     } else {
         let np = FreeImage_ConvertTo24Bits(p);
         FreeImage_Unload(p);
         if np.is_null() {
-            return 0;
+            return Err(ImageError::UnsupportedBitDepth(src_bits as u32));
         }
-        code = FreeImage_Save(format, np, c_path.as_ptr(), 0);
+        code = save(np);
         FreeImage_Unload(np);
     }
-    code
+    if code != 0 {
+        Ok(())
+    } else {
+        Err(codec_error(format))
+    }
+}
+
+unsafe fn save_raw_to_file(p: *mut c_void, c_path: &CString, src_bits: u8, format: FreeImageFormat,
+                           flags: c_int) -> Result<(), ImageError> {
+    save_raw(p, src_bits, &format,
+             |dib| FreeImage_Save(format.clone(), dib, c_path.as_ptr(), flags))
+}
+
+unsafe fn save_raw_to_memory(p: *mut c_void, src_bits: u8, format: FreeImageFormat, flags: c_int)
+    -> Result<Vec<u8>, ImageError> {
+    let stream = MemoryStream::new();
+    if stream.0.is_null() {
+        FreeImage_Unload(p);
+        return Err(ImageError::OutOfMemoryError);
+    }
+    save_raw(p, src_bits, &format,
+             |dib| FreeImage_SaveToMemory(format.clone(), dib, stream.0, flags))
+        .and_then(|_| stream.to_vec().ok_or(ImageError::OutOfMemoryError))
+}
+
+unsafe fn save_raw_to_writer<W: Write + Seek>(p: *mut c_void, writer: &mut W, src_bits: u8,
+                                               format: FreeImageFormat, flags: c_int)
+    -> Result<(), ImageError> {
+    let mut io = writer_callbacks::<W>();
+    let handle = writer as *mut W as *mut c_void;
+    save_raw(p, src_bits, &format,
+             |dib| FreeImage_SaveToHandle(format.clone(), dib, &mut io, handle, flags))
 }
 
 pub struct FreeImageIO;
 
+impl FreeImageIO {
+    /// Format of the file at `path`, detected from its content.
+    pub fn detect_format(path: &Path) -> Result<ImageFormat, ImageError> {
+        init();
+        let c_path = match path_to_cstring(path) {
+            Ok(c_path) => c_path,
+            Err(e) => return Err(e)
+        };
+        if let Err(e) = File::open(path) {
+            return Err(ImageError::Io(e).with_path(path));
+        }
+        let fif = unsafe { FreeImage_GetFileType(c_path.as_ptr(), 0) };
+        from_fif(&fif).ok_or_else(|| ImageError::UnknownImageFormat.with_path(path))
+    }
+
+    /// Format of an encoded image, detected from its signature bytes.
+    pub fn detect_format_from_bytes(data: &[u8]) -> Option<ImageFormat> {
+        init();
+        unsafe {
+            let stream = MemoryStream::wrap(data);
+            if stream.0.is_null() {
+                return None;
+            }
+            from_fif(&FreeImage_GetFileTypeFromMemory(stream.0, 0))
+        }
+    }
+
+    /// Whether this build of FreeImage can decode `format`.
+    pub fn supports_reading(format: ImageFormat) -> bool {
+        init();
+        unsafe { FreeImage_FIFSupportsReading(to_fif(format)) != 0 }
+    }
+
+    /// Whether this build of FreeImage can encode `format`.
+    pub fn supports_writing(format: ImageFormat) -> bool {
+        init();
+        unsafe { FreeImage_FIFSupportsWriting(to_fif(format)) != 0 }
+    }
+}
+
 macro_rules! define_io_for_image(
     ($itype:ident, $bits:expr) => (
         impl ImageIO<$itype> for FreeImageIO {
             fn from_path(path: &Path) -> Result<$itype, ImageError> {
                 init();
-                let p = unsafe { try_load_from_file(path, $bits, false) };
-                if p.is_null() {
-                    Err(ImageError::InvalidImage)
-                } else {
-                    let image = unsafe { from_raw(p) };
-                    Ok(image)
-                }
+                unsafe { try_load_from_file(path, $bits, false).map(|(p, _)| from_raw(p)) }
             }
 
             fn save(path: &Path, image: &$itype) -> Result<(), ImageError> {
+                Self::save_with_options(path, image, &SaveOptions::default())
+            }
+
+            fn save_with_options(path: &Path, image: &$itype, options: &SaveOptions)
+                -> Result<(), ImageError> {
                 init();
 
-                let c_path = CString::new(path.to_str().unwrap()).unwrap();
-                let format = unsafe { FreeImage_GetFIFFromFilename(c_path.as_ptr()) };
-                if format == ImageFormat::FIF_UNKNOWN {
-                    return Err(ImageError::UnknownImageFormat);
+                let c_path = match path_to_cstring(path) {
+                    Ok(c_path) => c_path,
+                    Err(e) => return Err(e)
+                };
+                let format = match options.format {
+                    Some(format) => to_fif(format),
+                    None => unsafe { FreeImage_GetFIFFromFilename(c_path.as_ptr()) }
+                };
+                if format == FreeImageFormat::FIF_UNKNOWN {
+                    return Err(ImageError::UnknownImageFormat.with_path(path));
                 }
+                let flags = match save_flags(&format, options) {
+                    Ok(flags) => flags,
+                    Err(e) => return Err(e.with_path(path))
+                };
 
                 let p = unsafe { to_raw(image) };
                 if p.is_null() {
                     return Err(ImageError::OutOfMemoryError);
                 }
 
-                if unsafe { save_raw_to_file(p, path, $bits, format) } != 0 {
-                    Ok(())
-                } else {
-                    Err(ImageError::UnknownError)
+                unsafe { save_raw_to_file(p, &c_path, $bits, format, flags) }
+                    .map_err(|e| e.with_path(path))
+            }
+
+            fn from_memory(data: &[u8]) -> Result<$itype, ImageError> {
+                init();
+                unsafe { try_load_from_memory(data, $bits, false).map(|(p, _)| from_raw(p)) }
+            }
+
+            fn encode(image: &$itype, format: ImageFormat) -> Result<Vec<u8>, ImageError> {
+                Self::encode_with_options(image, format, &SaveOptions::default())
+            }
+
+            fn encode_with_options(image: &$itype, format: ImageFormat, options: &SaveOptions)
+                -> Result<Vec<u8>, ImageError> {
+                init();
+
+                let format = match stream_format(format, options) {
+                    Ok(format) => format,
+                    Err(e) => return Err(e)
+                };
+                let flags = match save_flags(&format, options) {
+                    Ok(flags) => flags,
+                    Err(e) => return Err(e)
+                };
+                let p = unsafe { to_raw(image) };
+                if p.is_null() {
+                    return Err(ImageError::OutOfMemoryError);
+                }
+
+                unsafe { save_raw_to_memory(p, $bits, format, flags) }
+            }
+
+            fn from_reader<R: Read + Seek>(reader: &mut R) -> Result<$itype, ImageError> {
+                init();
+                unsafe { try_load_from_reader(reader, $bits, false).map(|(p, _)| from_raw(p)) }
+            }
+
+            fn save_to_writer<W: Write + Seek>(writer: &mut W, image: &$itype, format: ImageFormat)
+                -> Result<(), ImageError> {
+                Self::save_to_writer_with_options(writer, image, format, &SaveOptions::default())
+            }
+
+            fn save_to_writer_with_options<W: Write + Seek>(writer: &mut W, image: &$itype,
+                                                            format: ImageFormat,
+                                                            options: &SaveOptions)
+                -> Result<(), ImageError> {
+                init();
+
+                let format = match stream_format(format, options) {
+                    Ok(format) => format,
+                    Err(e) => return Err(e)
+                };
+                let flags = match save_flags(&format, options) {
+                    Ok(flags) => flags,
+                    Err(e) => return Err(e)
+                };
+                let p = unsafe { to_raw(image) };
+                if p.is_null() {
+                    return Err(ImageError::OutOfMemoryError);
                 }
+
+                unsafe { save_raw_to_writer(p, writer, $bits, format, flags) }
             }
         }
 
@@ -249,34 +781,39 @@ define_io_for_image!(ImageBgra, 32);
 impl ImagePing for FreeImageIO {
     fn ping_from_path(path: &Path) -> Result<ImageInfo, ImageError> {
         init();
-        let p = unsafe { try_load_from_file(path, 0, true) };
-        if p.is_null() {
-            Err(ImageError::InvalidImage)
-        } else {
-            unsafe {
-                let w = FreeImage_GetWidth(p);
-                let h = FreeImage_GetHeight(p);
-                let bpp = FreeImage_GetBPP(p);
-                let info = ImageInfo {
-                    signature: String::new(),
-                    width: w,
-                    height: h,
-                    bits_per_pixel: bpp,
-                };
-                FreeImage_Unload(p);
-                Ok(info)
-            }
+        let (p, format) = match unsafe { try_load_from_file(path, 0, true) } {
+            Ok(loaded) => loaded,
+            Err(e) => return Err(e)
+        };
+        unsafe {
+            let w = FreeImage_GetWidth(p);
+            let h = FreeImage_GetHeight(p);
+            let bpp = FreeImage_GetBPP(p);
+            let info = ImageInfo {
+                signature: format_name(&format),
+                width: w,
+                height: h,
+                bits_per_pixel: bpp,
+            };
+            FreeImage_Unload(p);
+            Ok(info)
         }
     }
 }
 
 #[cfg(test)]
 mod test {
+    use std::fs::File;
+    use std::io::{self, Cursor, ErrorKind, Read, Write, Seek, SeekFrom};
     use std::path::Path;
     use convert;
     use image::*;
-    use imageio::{ImageIO, ImagePing, ImageInfo};
+    use imageio::{ImageIO, ImagePing, ImageInfo, ImageFormat, SaveOptions, ChromaSubsampling,
+        TiffCompression};
     use imageio::FreeImageIO;
+    use super::{read_proc, write_proc, seek_proc, tell_proc, save_flags, FreeImageFormat,
+        to_fif, from_fif, stream_format};
+    use super::libc::c_void;
 
     #[test]
     fn test_load() {
@@ -306,6 +843,7 @@ mod test {
         assert_eq!(img.width, 150);
         assert_eq!(img.height, 120);
         assert_eq!(img.bits_per_pixel, 24);
+        assert_eq!(img.signature, "JPEG");
     }
 
     #[test]
@@ -322,5 +860,284 @@ mod test {
         let target = Path::new("/tmp/test-out-8.jpg");
         FreeImageIO::save(&target, &gray).unwrap();
     }
+    #[test]
+    fn test_memory() {
+        let mut data = Vec::new();
+        File::open("./tests/cat.jpg").unwrap().read_to_end(&mut data).unwrap();
+        let img: ImageBgr = FreeImageIO::from_memory(&data).unwrap();
+        assert_eq!(img.width(), 150);
+        assert_eq!(img.height(), 120);
+        let gray: ImageGray = FreeImageIO::from_memory(&data).unwrap();
+        assert_eq!(gray.size(), (150, 120));
+
+        // PNG is lossless
+        let png = FreeImageIO::encode(&img, ImageFormat::Png).unwrap();
+        assert_eq!(&png[1..4], b"PNG");
+        let decoded: ImageBgr = FreeImageIO::from_memory(&png).unwrap();
+        assert!(decoded.iter().zip(img.iter()).all(|(a, b)| a == b));
+
+        let bgra: ImageBgra = FreeImageIO::from_memory(&data).unwrap();
+        let jpeg = FreeImageIO::encode(&bgra, ImageFormat::Jpeg).unwrap();
+        assert_eq!(&jpeg[..2], &[0xff, 0xd8]);
+
+        let garbage: Result<ImageBgr, _> = FreeImageIO::from_memory(&[0u8; 16]);
+        assert!(garbage.is_err());
+    }
+
+    #[test]
+    fn test_callbacks() {
+        let mut cursor = Cursor::new(vec![1u8, 2, 3, 4, 5, 6, 7]);
+        let handle = &mut cursor as *mut Cursor<Vec<u8>> as *mut c_void;
+        let mut buf = [0u8; 4];
+        let p = buf.as_mut_ptr() as *mut c_void;
+        assert_eq!(read_proc::<Cursor<Vec<u8>>>(p, 2, 2, handle), 2);
+        assert_eq!(buf, [1, 2, 3, 4]);
+        // only whole items are counted
+        assert_eq!(read_proc::<Cursor<Vec<u8>>>(p, 2, 2, handle), 1);
+        assert_eq!(tell_proc::<Cursor<Vec<u8>>>(handle), 7);
+        assert_eq!(seek_proc::<Cursor<Vec<u8>>>(handle, -3, 2), 0);
+        assert_eq!(tell_proc::<Cursor<Vec<u8>>>(handle), 4);
+        assert_eq!(seek_proc::<Cursor<Vec<u8>>>(handle, 1, 1), 0);
+        assert_eq!(tell_proc::<Cursor<Vec<u8>>>(handle), 5);
+        assert_eq!(seek_proc::<Cursor<Vec<u8>>>(handle, -1, 0), -1);
+
+        assert_eq!(seek_proc::<Cursor<Vec<u8>>>(handle, 6, 0), 0);
+        let data = [9u8, 9, 9];
+        let p = data.as_ptr() as *mut c_void;
+        assert_eq!(write_proc::<Cursor<Vec<u8>>>(p, 1, 3, handle), 3);
+        assert_eq!(cursor.get_ref(), &vec![1, 2, 3, 4, 5, 6, 9, 9, 9]);
+
+        // panics are not propagated to FreeImage
+        struct Broken;
+        impl Read for Broken {
+            fn read(&mut self, _: &mut [u8]) -> io::Result<usize> { panic!("read") }
+        }
+        impl Write for Broken {
+            fn write(&mut self, _: &[u8]) -> io::Result<usize> { panic!("write") }
+            fn flush(&mut self) -> io::Result<()> { Ok(()) }
+        }
+        impl Seek for Broken {
+            fn seek(&mut self, _: SeekFrom) -> io::Result<u64> { panic!("seek") }
+        }
+        let mut broken = Broken;
+        let handle = &mut broken as *mut Broken as *mut c_void;
+        let mut buf = [0u8; 4];
+        assert_eq!(read_proc::<Broken>(buf.as_mut_ptr() as *mut c_void, 1, 4, handle), 0);
+        assert_eq!(write_proc::<Broken>(buf.as_mut_ptr() as *mut c_void, 1, 4, handle), 0);
+        assert_eq!(seek_proc::<Broken>(handle, 0, 0), -1);
+        assert_eq!(tell_proc::<Broken>(handle), -1);
+
+        // positions a `c_long` can not hold
+        struct Far;
+        impl Seek for Far {
+            fn seek(&mut self, _: SeekFrom) -> io::Result<u64> { Ok(u64::max_value()) }
+        }
+        let mut far = Far;
+        assert_eq!(tell_proc::<Far>(&mut far as *mut Far as *mut c_void), -1);
+    }
+
+    #[test]
+    fn test_reader_writer() {
+        let mut file = File::open("./tests/cat.jpg").unwrap();
+        let img: ImageBgr = FreeImageIO::from_reader(&mut file).unwrap();
+        assert_eq!(img.size(), (150, 120));
+
+        // images do not need to start at the beginning of the stream
+        let mut out = Cursor::new(b"header".to_vec());
+        out.seek(SeekFrom::End(0)).unwrap();
+        FreeImageIO::save_to_writer(&mut out, &img, ImageFormat::Png).unwrap();
+        out.seek(SeekFrom::Start(6)).unwrap();
+        let decoded: ImageBgr = FreeImageIO::from_reader(&mut out).unwrap();
+        assert!(decoded.iter().zip(img.iter()).all(|(a, b)| a == b));
+
+        let mut data = Vec::new();
+        File::open("./tests/cat.jpg").unwrap().read_to_end(&mut data).unwrap();
+        let gray: ImageGray = FreeImageIO::from_reader(&mut Cursor::new(data)).unwrap();
+        assert_eq!(gray.size(), (150, 120));
+    }
+
+    #[test]
+    fn test_errors() {
+        let missing = Path::new("./tests/missing.jpg");
+        match FreeImageIO::from_path(missing) as Result<ImageBgr, _> {
+            Err(ImageError::File { ref path, ref error }) => {
+                assert_eq!(path, missing);
+                match **error {
+                    ImageError::Io(ref e) => assert_eq!(e.kind(), ErrorKind::NotFound),
+                    ref e => panic!("unexpected {:?}", e)
+                }
+            },
+            r => panic!("unexpected {:?}", r.map(|_| ()))
+        }
+        assert!(FreeImageIO::ping_from_path(missing).err().unwrap().to_string()
+                .starts_with("./tests/missing.jpg: "));
+
+        // not UTF-8, must not panic
+        #[cfg(unix)]
+        {
+            use std::ffi::OsStr;
+            use std::os::unix::ffi::OsStrExt;
+            let odd = Path::new(OsStr::from_bytes(b"/tmp/\xff\xfe.png"));
+            let r: Result<ImageGray, _> = FreeImageIO::from_path(odd);
+            assert_eq!(r.unwrap_err().path(), Some(odd));
+        }
+
+        let img = ImageGray::new(4, 4);
+        let r = FreeImageIO::save(Path::new("/tmp/test-out.unknown-ext"), &img);
+        match r {
+            Err(ImageError::File { ref error, .. }) => match **error {
+                ImageError::UnknownImageFormat => (),
+                ref e => panic!("unexpected {:?}", e)
+            },
+            r => panic!("unexpected {:?}", r)
+        }
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        let unknown: Result<ImageBgr, _> = FreeImageIO::from_memory(b"definitely not an image");
+        match unknown {
+            Err(ImageError::UnknownImageFormat) => (),
+            r => panic!("unexpected {:?}", r.map(|_| ()))
+        }
+
+        // a truncated JPEG is detected but fails to decode
+        let mut data = Vec::new();
+        File::open("./tests/cat.jpg").unwrap().read_to_end(&mut data).unwrap();
+        data.truncate(64);
+        match FreeImageIO::from_memory(&data) as Result<ImageBgr, _> {
+            Err(ImageError::Codec { ref format, .. }) => assert_eq!(format, "JPEG"),
+            r => panic!("unexpected {:?}", r.map(|_| ()))
+        }
+    }
+
+    #[test]
+    fn test_save_flags() {
+        let options = SaveOptions {
+            jpeg_quality: Some(90),
+            jpeg_subsampling: Some(ChromaSubsampling::S444),
+            jpeg_progressive: true,
+            png_compression: Some(0),
+            tiff_compression: Some(TiffCompression::Lzw),
+            webp_quality: Some(60),
+            ..SaveOptions::default()
+        };
+        assert_eq!(save_flags(&FreeImageFormat::FIF_JPEG, &options).unwrap(),
+                   90 | 0x10000 | 0x2000);
+        assert_eq!(save_flags(&FreeImageFormat::FIF_PNG, &options).unwrap(), 0x0100);
+        assert_eq!(save_flags(&FreeImageFormat::FIF_TIFF, &options).unwrap(), 0x4000);
+        assert_eq!(save_flags(&FreeImageFormat::FIF_WEBP, &options).unwrap(), 60);
+        assert_eq!(save_flags(&FreeImageFormat::FIF_BMP, &options).unwrap(), 0);
+
+        let lossless = SaveOptions { webp_lossless: true, png_compression: Some(9),
+                                     ..options.clone() };
+        assert_eq!(save_flags(&FreeImageFormat::FIF_WEBP, &lossless).unwrap(), 0x0100);
+        assert_eq!(save_flags(&FreeImageFormat::FIF_PNG, &lossless).unwrap(), 9);
+        assert_eq!(save_flags(&FreeImageFormat::FIF_JPEG, &SaveOptions::default()).unwrap(), 0);
+
+        // out of range values are an error for the format they apply to
+        let invalid = |format: FreeImageFormat, options: SaveOptions| {
+            match save_flags(&format, &options) {
+                Err(ImageError::InvalidOption(_)) => true,
+                _ => false
+            }
+        };
+        assert!(invalid(FreeImageFormat::FIF_JPEG,
+                        SaveOptions { jpeg_quality: Some(0), ..options.clone() }));
+        assert!(invalid(FreeImageFormat::FIF_JPEG,
+                        SaveOptions { jpeg_quality: Some(101), ..options.clone() }));
+        assert!(invalid(FreeImageFormat::FIF_PNG,
+                        SaveOptions { png_compression: Some(10), ..options.clone() }));
+        assert!(invalid(FreeImageFormat::FIF_WEBP,
+                        SaveOptions { webp_quality: Some(0), ..options.clone() }));
+        assert!(!invalid(FreeImageFormat::FIF_PNG,
+                         SaveOptions { jpeg_quality: Some(0), ..options.clone() }));
+        assert!(!invalid(FreeImageFormat::FIF_WEBP,
+                         SaveOptions { webp_quality: Some(0), webp_lossless: true, ..options }));
+
+        // the format of the streaming encoders is their argument
+        let png = SaveOptions { format: Some(ImageFormat::Png), ..SaveOptions::default() };
+        assert_eq!(stream_format(ImageFormat::Png, &png).unwrap(), FreeImageFormat::FIF_PNG);
+        assert_eq!(stream_format(ImageFormat::Jpeg, &SaveOptions::default()).unwrap(),
+                   FreeImageFormat::FIF_JPEG);
+        match stream_format(ImageFormat::Jpeg, &png) {
+            Err(ImageError::InvalidOption(_)) => (),
+            r => panic!("unexpected {:?}", r)
+        }
+    }
+
+    #[test]
+    fn test_save_with_options() {
+        let img: ImageBgr = FreeImageIO::from_path(Path::new("./tests/cat.jpg")).unwrap();
+        let size = |path: &str, quality: u8| {
+            let options = SaveOptions { jpeg_quality: Some(quality), ..SaveOptions::default() };
+            FreeImageIO::save_with_options(Path::new(path), &img, &options).unwrap();
+            File::open(path).unwrap().metadata().unwrap().len()
+        };
+        assert!(size("/tmp/test-out-q10.jpg", 10) < size("/tmp/test-out-q95.jpg", 95));
+
+        // the format overrides the extension
+        let options = SaveOptions {
+            format: Some(ImageFormat::Png),
+            png_compression: Some(9),
+            ..SaveOptions::default()
+        };
+        let target = Path::new("/tmp/test-out-png.dat");
+        FreeImageIO::save_with_options(target, &img, &options).unwrap();
+        let mut data = Vec::new();
+        File::open(target).unwrap().read_to_end(&mut data).unwrap();
+        assert_eq!(&data[1..4], b"PNG");
+        let decoded: ImageBgr = FreeImageIO::from_path(target).unwrap();
+        assert!(decoded.iter().zip(img.iter()).all(|(a, b)| a == b));
+
+        let encoded = |quality: u8| {
+            let options = SaveOptions { jpeg_quality: Some(quality), ..SaveOptions::default() };
+            FreeImageIO::encode_with_options(&img, ImageFormat::Jpeg, &options)
+        };
+        assert!(encoded(10).unwrap().len() < encoded(95).unwrap().len());
+        let mut out = Cursor::new(Vec::new());
+        let options = SaveOptions { jpeg_quality: Some(10), ..SaveOptions::default() };
+        FreeImageIO::save_to_writer_with_options(&mut out, &img, ImageFormat::Jpeg, &options)
+            .unwrap();
+        assert_eq!(out.into_inner(), encoded(10).unwrap());
+
+        // invalid options are reported before anything is written
+        let options = SaveOptions { jpeg_quality: Some(0), ..SaveOptions::default() };
+        match encoded(0) {
+            Err(ImageError::InvalidOption(_)) => (),
+            r => panic!("unexpected {:?}", r.map(|_| ()))
+        }
+        let r = FreeImageIO::save_with_options(Path::new("/tmp/test-out-q0.jpg"), &img, &options);
+        assert_eq!(r.unwrap_err().path(), Some(Path::new("/tmp/test-out-q0.jpg")));
+        assert!(!Path::new("/tmp/test-out-q0.jpg").exists());
+    }
+
+    #[test]
+    fn test_format_mapping() {
+        assert_eq!(to_fif(ImageFormat::Bmp), FreeImageFormat::FIF_BMP);
+        assert_eq!(to_fif(ImageFormat::Jxr), FreeImageFormat::FIF_JXR);
+        assert_eq!(from_fif(&FreeImageFormat::FIF_PNG), Some(ImageFormat::Png));
+        assert_eq!(from_fif(&FreeImageFormat::FIF_UNKNOWN), None);
+        for &format in [ImageFormat::Jpeg, ImageFormat::Tiff, ImageFormat::Webp].iter() {
+            assert_eq!(from_fif(&to_fif(format)), Some(format));
+        }
+    }
+
+    #[test]
+    fn test_detect_format() {
+        let path = Path::new("./tests/cat.jpg");
+        assert_eq!(FreeImageIO::detect_format(path).unwrap(), ImageFormat::Jpeg);
+        assert!(FreeImageIO::detect_format(Path::new("./tests/missing.jpg")).is_err());
+
+        let mut data = Vec::new();
+        File::open(path).unwrap().read_to_end(&mut data).unwrap();
+        assert_eq!(FreeImageIO::detect_format_from_bytes(&data), Some(ImageFormat::Jpeg));
+        assert_eq!(FreeImageIO::detect_format_from_bytes(b"definitely not an image"), None);
+
+        assert!(FreeImageIO::supports_reading(ImageFormat::Png));
+        assert!(FreeImageIO::supports_writing(ImageFormat::Png));
+        assert!(FreeImageIO::supports_reading(ImageFormat::Raw));
+        assert!(!FreeImageIO::supports_writing(ImageFormat::Raw));
+    }
 }
 
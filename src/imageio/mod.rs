@@ -1,17 +1,116 @@
+use std::io::{Read, Write, Seek};
 use std::path::Path;
 
 use image::{ImageError, GenericImage};
 
 pub struct ImageInfo {
+    /// Name of the format, like "JPEG"
     pub signature: String,
     pub width: u32,
     pub height: u32,
     pub bits_per_pixel: u32,
 }
 
+/// File formats known to the backends, not all of them can be read or
+/// written by every build.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ImageFormat {
+    Bmp,
+    Ico,
+    Jpeg,
+    Jng,
+    Koala,
+    Lbm,
+    Mng,
+    Pbm,
+    PbmRaw,
+    Pcd,
+    Pcx,
+    Pgm,
+    PgmRaw,
+    Png,
+    Ppm,
+    PpmRaw,
+    Ras,
+    Targa,
+    Tiff,
+    Wbmp,
+    Psd,
+    Cut,
+    Xbm,
+    Xpm,
+    Dds,
+    Gif,
+    Hdr,
+    FaxG3,
+    Sgi,
+    Exr,
+    J2k,
+    Jp2,
+    Pfm,
+    Pict,
+    Raw,
+    Webp,
+    Jxr
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChromaSubsampling {
+    S411,
+    S420,
+    S422,
+    S444
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TiffCompression {
+    None,
+    PackBits,
+    Lzw,
+    Deflate,
+    AdobeDeflate
+}
+
+/// Encoder settings, `None` leaves the codec default. Options of other
+/// formats than the saved one are ignored.
+#[derive(Debug, Clone, Default)]
+pub struct SaveOptions {
+    /// Format overriding the one of the file name extension. The streaming
+    /// `encode_with_options` and `save_to_writer_with_options` take theirs
+    /// as argument and fail with `InvalidOption` if this one differs.
+    pub format: Option<ImageFormat>,
+    /// JPEG quality, 1 to 100
+    pub jpeg_quality: Option<u8>,
+    pub jpeg_subsampling: Option<ChromaSubsampling>,
+    pub jpeg_progressive: bool,
+    /// Optimal Huffman tables, smaller files for a slower encoding
+    pub jpeg_optimize: bool,
+    /// zlib level, 0 (none) to 9 (best)
+    pub png_compression: Option<u8>,
+    pub tiff_compression: Option<TiffCompression>,
+    /// Lossy WebP quality, 1 to 100
+    pub webp_quality: Option<u8>,
+    pub webp_lossless: bool,
+}
+
 pub trait ImageIO<T: GenericImage> {
     fn from_path(path: &Path) -> Result<T, ImageError>;
     fn save(path: &Path, image: &T) -> Result<(), ImageError>;
+    fn save_with_options(path: &Path, image: &T, options: &SaveOptions)
+        -> Result<(), ImageError>;
+    /// Decodes an encoded image, the format is detected from its signature.
+    fn from_memory(data: &[u8]) -> Result<T, ImageError>;
+    fn encode(image: &T, format: ImageFormat) -> Result<Vec<u8>, ImageError>;
+    fn encode_with_options(image: &T, format: ImageFormat, options: &SaveOptions)
+        -> Result<Vec<u8>, ImageError>;
+    /// Decodes an image starting at the current position of `reader`.
+    fn from_reader<R: Read + Seek>(reader: &mut R) -> Result<T, ImageError>;
+    /// Encodes `image` at the current position of `writer`.
+    fn save_to_writer<W: Write + Seek>(writer: &mut W, image: &T, format: ImageFormat)
+        -> Result<(), ImageError>;
+    fn save_to_writer_with_options<W: Write + Seek>(writer: &mut W, image: &T, format: ImageFormat,
+                                                    options: &SaveOptions)
+        -> Result<(), ImageError>;
 }
 
 pub trait ImagePing {
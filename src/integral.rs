@@ -0,0 +1,346 @@
+//! Summed-area tables with the rectangle sums they answer, and box filters.
+//!
+//! The tables of a `w` x `h` image are `w + 1` x `h + 1`, entry (x, y)
+//! holds the sum over the pixels above and left of (x, y), so the first
+//! row and column are zero.
+//!
+//! `box_filter` and `blur` do not build a table, they slide running sums
+//! of the window rows kept per column. That is O(1) per pixel as well but
+//! only needs one row of sums instead of a full table of `f64`.
+
+use image::*;
+use geo::Recti;
+use traits::{Primitive, SaturateCast};
+use num::traits::ToPrimitive;
+use border::{BorderType, border_index, border_map, border_value};
+
+fn integral_with<S, I, F>(src: &I, f: F) -> Image<Gray<f64>>
+    where S: Primitive,
+          I: GenericImage<Pixel=Gray<S>>,
+          F: Fn(f64) -> f64
+{
+    let (w, h) = src.size();
+    let mut dst = Image::<Gray<f64>>::new(w + 1, h + 1);
+    for y in 0..h {
+        let psrc = src.row(y);
+        let mut s = 0f64;
+        for x in 0..w as usize {
+            s += f(psrc[x][0].to_f64().unwrap());
+            let above = dst.row(y)[x + 1][0];
+            dst.row_mut(y + 1)[x + 1] = Gray([above + s]);
+        }
+    }
+    dst
+}
+
+/// Returns the summed-area table of `src`.
+pub fn integral<S, I>(src: &I) -> Image<Gray<f64>>
+    where S: Primitive,
+          I: GenericImage<Pixel=Gray<S>>
+{
+    integral_with(src, |v| v)
+}
+
+/// Returns the summed-area table of the squared pixels, used with
+/// `integral` for local variances.
+pub fn integral_squared<S, I>(src: &I) -> Image<Gray<f64>>
+    where S: Primitive,
+          I: GenericImage<Pixel=Gray<S>>
+{
+    integral_with(src, |v| v * v)
+}
+
+/// Returns the table of sums over the triangles rotated by 45 degrees,
+/// entry (x, y) holds the sum over the pixels (px, py) with `py < y` and
+/// `|px - x + 1| <= y - 1 - py`. See `tilted_rect_sum`.
+pub fn integral_tilted<S, I>(src: &I) -> Image<Gray<f64>>
+    where S: Primitive,
+          I: GenericImage<Pixel=Gray<S>>
+{
+    let (w, h) = (src.width() as usize, src.height() as usize);
+    // the triangles reach up to h columns outside of the image
+    let pad = h + 1;
+    let tw = w + 1 + 2 * pad;
+    let mut t = vec![0f64; tw * (h + 1)];
+    let pixel = |x: isize, y: usize| {
+        if x < 0 || x >= w as isize {
+            0.0
+        } else {
+            src.row(y as u32)[x as usize][0].to_f64().unwrap()
+        }
+    };
+    for y in 1..h + 1 {
+        for tx in 1..tw - 1 {
+            let x = tx as isize - pad as isize;
+            let mut v = t[(y - 1) * tw + tx - 1] + t[(y - 1) * tw + tx + 1] + pixel(x - 1, y - 1);
+            if y >= 2 {
+                v += pixel(x - 1, y - 2) - t[(y - 2) * tw + tx];
+            }
+            t[y * tw + tx] = v;
+        }
+    }
+    let mut dst = Image::<Gray<f64>>::new(w as u32 + 1, h as u32 + 1);
+    for y in 0..h + 1 {
+        for (d, s) in dst.row_mut(y as u32).iter_mut().zip(t[y * tw + pad..].iter()) {
+            *d = Gray([*s]);
+        }
+    }
+    dst
+}
+
+/// Sum over `rect` from a table returned by `integral` or
+/// `integral_squared`.
+#[inline]
+pub fn rect_sum(sum: &Image<Gray<f64>>, rect: &Recti) -> f64 {
+    assert!(rect.x >= 0 && rect.y >= 0 && rect.width >= 0 && rect.height >= 0 &&
+            rect.x + rect.width < sum.width() as i32 && rect.y + rect.height < sum.height() as i32,
+            "region {} out of the integral image", rect);
+    let (x0, y0) = (rect.x as usize, rect.y as u32);
+    let (x1, y1) = (x0 + rect.width as usize, y0 + rect.height as u32);
+    let (top, bottom) = (sum.row(y0), sum.row(y1));
+    bottom[x1][0] - bottom[x0][0] - top[x1][0] + top[x0][0]
+}
+
+/// Mean and variance of the pixels in `rect`, from the tables returned
+/// by `integral` and `integral_squared`.
+pub fn rect_mean_variance(sum: &Image<Gray<f64>>, sqsum: &Image<Gray<f64>>,
+                          rect: &Recti) -> (f64, f64) {
+    let n = (rect.width * rect.height) as f64;
+    if n == 0.0 {
+        return (0.0, 0.0);
+    }
+    let mean = rect_sum(sum, rect) / n;
+    let var = rect_sum(sqsum, rect) / n - mean * mean;
+    (mean, var.max(0.0))
+}
+
+/// Sum over a rectangle rotated by 45 degrees, from a table returned by
+/// `integral_tilted`. (`rect.x`, `rect.y`) is its top corner, its width
+/// runs down and right, its height down and left.
+pub fn tilted_rect_sum(tilted: &Image<Gray<f64>>, rect: &Recti) -> f64 {
+    let (x, y, w, h) = (rect.x, rect.y, rect.width, rect.height);
+    assert!(w >= 0 && h >= 0 && x - h >= 0 && y >= 0 &&
+            x + w < tilted.width() as i32 && y + w + h < tilted.height() as i32,
+            "tilted region {} out of the integral image", rect);
+    let t = |x: i32, y: i32| tilted.row(y as u32)[x as usize][0];
+    t(x + w - h, y + w + h) - t(x + w, y + w) - t(x - h, y + h) + t(x, y)
+}
+
+/// Sum over the `kernel_width` x `kernel_height` window centered on each
+/// pixel, divided by the window area with `normalize`. The results
+/// saturate to the output type, the cost per pixel does not depend on the
+/// window size and only one row of column sums is kept.
+pub fn box_filter<T, U, I>(src: &I, kernel_width: u32, kernel_height: u32, normalize: bool,
+                           border: BorderType) -> Image<U>
+    where T: Pixel,
+          U: Pixel,
+          I: GenericImage<Pixel=T>
+{
+    assert!(kernel_width >= 1 && kernel_height >= 1);
+    assert_eq!(T::channels(), U::channels());
+    let (w, h) = (src.width() as usize, src.height() as usize);
+    let (kw, kh) = (kernel_width as usize, kernel_height as usize);
+    let channels = T::channels();
+    if w == 0 || h == 0 {
+        return Image::new(w as u32, h as u32);
+    }
+    let cval: f64 = border_value(border);
+
+    // running sums of the `kh` rows in the window for every column of the
+    // source extended by the border, moved down one row per output row
+    let ew = w + kw - 1;
+    let cols = border_map(w as u32, kw as u32, (kw / 2) as i32, border);
+    let add_row = |sums: &mut [f64], ey: usize, sign: f64| {
+        match border_index(ey as i32 - (kh / 2) as i32, h as i32, border) {
+            Some(y) => {
+                let row = src.row(y as u32);
+                for (ex, col) in cols.iter().enumerate() {
+                    for c in 0..channels {
+                        sums[ex * channels + c] += sign * match *col {
+                            Some(x) => row[x].raw()[c].to_f64().unwrap(),
+                            None => cval
+                        };
+                    }
+                }
+            },
+            None => {
+                for v in sums.iter_mut() {
+                    *v += sign * cval;
+                }
+            }
+        }
+    };
+
+    let scale = if normalize { 1.0 / (kw * kh) as f64 } else { 1.0 };
+    let mut dst: Image<U> = Image::new(w as u32, h as u32);
+    let mut sums = vec![0f64; ew * channels];
+    let mut px = vec![0f64; channels];
+    for y in 0..h {
+        if y == 0 {
+            for ey in 0..kh {
+                add_row(&mut sums, ey, 1.0);
+            }
+        } else {
+            add_row(&mut sums, y - 1, -1.0);
+            add_row(&mut sums, y + kh - 1, 1.0);
+        }
+        let pdst = dst.row_mut(y as u32);
+        for c in 0..channels {
+            px[c] = (0..kw).fold(0f64, |s, ex| s + sums[ex * channels + c]);
+        }
+        for x in 0..w {
+            if x > 0 {
+                for c in 0..channels {
+                    px[c] += sums[(x + kw - 1) * channels + c] - sums[(x - 1) * channels + c];
+                }
+            }
+            for c in 0..channels {
+                pdst[x].raw_mut()[c] = SaturateCast::saturate_cast((px[c] * scale) as f32);
+            }
+        }
+    }
+    dst
+}
+
+/// Mean over the `kernel_width` x `kernel_height` window centered on each
+/// pixel.
+pub fn blur<T, I>(src: &I, kernel_width: u32, kernel_height: u32, border: BorderType) -> Image<T>
+    where T: Pixel,
+          I: GenericImage<Pixel=T>
+{
+    box_filter(src, kernel_width, kernel_height, true, border)
+}
+
+#[cfg(test)]
+mod test {
+    use image::*;
+    use geo::Recti;
+    use border::BorderType;
+    use super::*;
+
+    fn sample() -> ImageGray {
+        ImageGray::from_fn(7, 5, |x, y| Gray([((x * 31 + y * 17) % 23) as u8]))
+    }
+
+    #[test]
+    fn test_integral() {
+        let src = sample();
+        let sum = integral(&src);
+        let sqsum = integral_squared(&src);
+        assert_eq!(sum.size(), (8, 6));
+        assert!(sum.row(0).iter().all(|p| p[0] == 0.0));
+        for y in 0..6 {
+            for x in 0..8 {
+                let (mut s, mut sq) = (0f64, 0f64);
+                for py in 0..y {
+                    for px in 0..x {
+                        let v = src[(px, py)][0] as f64;
+                        s += v;
+                        sq += v * v;
+                    }
+                }
+                assert_eq!(sum[(x, y)][0], s);
+                assert_eq!(sqsum[(x, y)][0], sq);
+            }
+        }
+
+        let r = Recti::new(2, 1, 3, 4);
+        let mut s = 0f64;
+        for (x, y, p) in src.iter() {
+            if x >= 2 && x < 5 && y >= 1 && y < 5 {
+                s += p[0] as f64;
+            }
+        }
+        assert_eq!(rect_sum(&sum, &r), s);
+        assert_eq!(rect_sum(&sum, &Recti::new(0, 0, 7, 5)), sum[(7, 5)][0]);
+        assert_eq!(rect_sum(&sum, &Recti::new(3, 3, 0, 2)), 0.0);
+
+        let flat = ImageGrayf::from_pixel(4, 4, Gray([2.5]));
+        let (mean, var) = rect_mean_variance(&integral(&flat), &integral_squared(&flat),
+                                             &Recti::new(1, 1, 2, 3));
+        assert_eq!((mean, var), (2.5, 0.0));
+        let (mean, var) = rect_mean_variance(&integral(&src), &integral_squared(&src),
+                                             &Recti::new(0, 0, 2, 1));
+        let (a, b) = (src[(0, 0)][0] as f64, src[(1, 0)][0] as f64);
+        assert_eq!(mean, (a + b) / 2.0);
+        assert!((var - (a - b) * (a - b) / 4.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_integral_tilted() {
+        let src = sample();
+        let tilted = integral_tilted(&src);
+        assert_eq!(tilted.size(), (8, 6));
+        for ty in 0..6i32 {
+            for tx in 0..8i32 {
+                let mut s = 0f64;
+                for (x, y, p) in src.iter() {
+                    let (x, y) = (x as i32, y as i32);
+                    if y < ty && (x - tx + 1).abs() <= ty - 1 - y {
+                        s += p[0] as f64;
+                    }
+                }
+                assert_eq!(tilted[(tx as u32, ty as u32)][0], s, "{} {}", tx, ty);
+            }
+        }
+
+        // the rotated rectangle covers x + y in [u0 - 1, u0 + 2w - 2] and
+        // x - y in [v0 - 2h, v0 - 1]
+        let (x0, y0, w, h) = (3, 0, 2, 2);
+        let (u0, v0) = (x0 + y0, x0 - y0);
+        let mut s = 0f64;
+        for (x, y, p) in src.iter() {
+            let (u, v) = (x as i32 + y as i32, x as i32 - y as i32);
+            if u >= u0 - 1 && u <= u0 + 2 * w - 2 && v >= v0 - 2 * h && v <= v0 - 1 {
+                s += p[0] as f64;
+            }
+        }
+        assert_eq!(tilted_rect_sum(&tilted, &Recti::new(x0, y0, w, h)), s);
+    }
+
+    #[test]
+    fn test_box_filter() {
+        let src = sample();
+        let sum: Image<Gray<f32>> = box_filter(&src, 3, 3, false, BorderType::Constant(0.0));
+        let (w, h) = (src.width() as i32, src.height() as i32);
+        for (x, y, p) in sum.iter() {
+            let mut s = 0f32;
+            for dy in -1..2 {
+                for dx in -1..2 {
+                    let (sx, sy) = (x as i32 + dx, y as i32 + dy);
+                    if sx >= 0 && sy >= 0 && sx < w && sy < h {
+                        s += src[(sx as u32, sy as u32)][0] as f32;
+                    }
+                }
+            }
+            assert_eq!(p[0], s);
+        }
+
+        // same as the separable box kernel for every border
+        let srcf = Image::from_fn(9, 6, |x, y| Bgr([x as f32, y as f32, (x * y % 5) as f32]));
+        for &border in [BorderType::Replicate, BorderType::Constant(2.0), BorderType::Reflect,
+                        BorderType::Reflect101, BorderType::Wrap].iter() {
+            let a = blur(&srcf, 5, 3, border);
+            let b = ::conv::conv2d_sep_with_border(&srcf, &[0.2; 5], &[1.0 / 3.0; 3], border);
+            for ((_, _, p), (_, _, q)) in a.iter().zip(b.iter()) {
+                assert!((0..3).all(|c| (p[c] - q[c]).abs() < 1e-4), "{:?}", border);
+            }
+        }
+
+        let flat = ImageGray::from_pixel(6, 6, Gray([200]));
+        let out: ImageGray = box_filter(&flat, 3, 3, false, BorderType::Replicate);
+        assert!(out.iter().all(|(_, _, p)| p[0] == 255));
+        let out = blur(&flat, 4, 2, BorderType::Reflect101);
+        assert!(out.iter().all(|(_, _, p)| p[0] == 200));
+
+        // windows larger than the image and empty images
+        let a = blur(&srcf, 3, 9, BorderType::Wrap);
+        let b = ::conv::conv2d_sep_with_border(&srcf, &[1.0 / 3.0; 3], &[1.0 / 9.0; 9],
+                                               BorderType::Wrap);
+        for ((_, _, p), (_, _, q)) in a.iter().zip(b.iter()) {
+            assert!((0..3).all(|c| (p[c] - q[c]).abs() < 1e-4));
+        }
+        assert_eq!(blur(&ImageGray::new(0, 4), 3, 3, BorderType::Replicate).size(), (0, 4));
+        assert_eq!(blur(&ImageGray::new(4, 0), 3, 3, BorderType::Replicate).size(), (4, 0));
+    }
+}
@@ -4,18 +4,32 @@
 extern crate num;
 extern crate libc;
 extern crate nalgebra;
+#[cfg(feature = "parallel")]
+extern crate rayon;
 
 mod traits;
+mod par;
+mod simd;
 pub mod image;
 pub mod imageio;
 pub mod convert;
+pub mod yuv;
 pub mod transform;
 pub mod geo;
 pub mod math;
 pub mod conv;
+pub mod border;
+pub mod gradient;
+pub mod integral;
+pub mod median;
+pub mod edge_preserving;
+pub mod morphology;
+pub mod threshold;
 
 pub use imageio::{
     ImageIO,
-    FreeImageIO
+    FreeImageIO,
+    ImageFormat,
+    SaveOptions
 };
 
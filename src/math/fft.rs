@@ -0,0 +1,115 @@
+//! Radix-2 fast Fourier transform.
+
+use std::f64::consts::PI;
+use num::complex::Complex64;
+
+/// Returns the smallest power of two not less than `n`.
+#[inline]
+pub fn fft_size(n: usize) -> usize {
+    n.max(1).next_power_of_two()
+}
+
+/// In-place FFT of `data`, whose length must be a power of two. The
+/// inverse transform is not normalized.
+pub fn fft(data: &mut [Complex64], inverse: bool) {
+    let n = data.len();
+    assert!(n.is_power_of_two(), "fft length {} is not a power of two", n);
+
+    // bit reversal permutation
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            data.swap(i, j);
+        }
+    }
+
+    let sign = if inverse { 1.0 } else { -1.0 };
+    let mut len = 2;
+    while len <= n {
+        let angle = sign * 2.0 * PI / len as f64;
+        let wlen = Complex64::new(angle.cos(), angle.sin());
+        for start in (0..n).step_by(len) {
+            let mut w = Complex64::new(1.0, 0.0);
+            for k in 0..len / 2 {
+                let a = data[start + k];
+                let b = data[start + k + len / 2] * w;
+                data[start + k] = a + b;
+                data[start + k + len / 2] = a - b;
+                w = w * wlen;
+            }
+        }
+        len <<= 1;
+    }
+}
+
+/// 2D FFT of a row-major `width` x `height` buffer, both dimensions must
+/// be powers of two. The inverse transform is not normalized.
+pub fn fft2d(data: &mut [Complex64], width: usize, height: usize, inverse: bool) {
+    assert_eq!(data.len(), width * height);
+    for row in data.chunks_mut(width) {
+        fft(row, inverse);
+    }
+    let mut col = vec![Complex64::new(0.0, 0.0); height];
+    for x in 0..width {
+        for y in 0..height {
+            col[y] = data[y * width + x];
+        }
+        fft(&mut col, inverse);
+        for y in 0..height {
+            data[y * width + x] = col[y];
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::f64::consts::PI;
+    use num::complex::Complex64;
+
+    fn dft(data: &[Complex64]) -> Vec<Complex64> {
+        let n = data.len();
+        (0..n).map(|k| {
+            data.iter().enumerate().fold(Complex64::new(0.0, 0.0), |s, (i, v)| {
+                let a = -2.0 * PI * (i * k) as f64 / n as f64;
+                s + v * Complex64::new(a.cos(), a.sin())
+            })
+        }).collect()
+    }
+
+    #[test]
+    fn test_fft() {
+        let src: Vec<Complex64> = (0..16)
+            .map(|i| Complex64::new((i * 7 % 5) as f64, (i % 3) as f64 - 1.0))
+            .collect();
+        let mut data = src.clone();
+        fft(&mut data, false);
+        for (a, b) in data.iter().zip(dft(&src).iter()) {
+            assert!((a - b).norm() < 1e-9);
+        }
+        fft(&mut data, true);
+        for (a, b) in data.iter().zip(src.iter()) {
+            assert!((a / 16.0 - b).norm() < 1e-9);
+        }
+        assert_eq!(fft_size(0), 1);
+        assert_eq!(fft_size(17), 32);
+    }
+
+    #[test]
+    fn test_fft2d() {
+        // a delta transforms to a constant
+        let mut data = vec![Complex64::new(0.0, 0.0); 8 * 4];
+        data[0] = Complex64::new(1.0, 0.0);
+        fft2d(&mut data, 8, 4, false);
+        assert!(data.iter().all(|v| (v - Complex64::new(1.0, 0.0)).norm() < 1e-12));
+        fft2d(&mut data, 8, 4, true);
+        assert!((data[0] - Complex64::new(32.0, 0.0)).norm() < 1e-9);
+        assert!(data[1..].iter().all(|v| v.norm() < 1e-9));
+    }
+}
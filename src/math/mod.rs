@@ -1,2 +1,3 @@
 pub mod affine;
 pub mod utils;
+pub mod fft;
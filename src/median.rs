@@ -0,0 +1,271 @@
+//! Median filtering.
+
+use std::cmp::Ordering;
+use image::*;
+use traits::Primitive;
+use border::{BorderType, border_map, border_value};
+use par::for_each_row_mut_with;
+
+/// Subpixel types `median_blur` is implemented for. `u8` images use a
+/// constant-time histogram algorithm, the others sort each window.
+pub trait MedianSubpixel: Primitive {
+    fn median_blur<T, I>(src: &I, ksize: u32, border: BorderType) -> Image<T>
+        where T: Pixel<Subpixel=Self>,
+              I: GenericImage<Pixel=T>;
+}
+
+/// Replaces every channel of each pixel by its median over the `ksize` x
+/// `ksize` window centered on the pixel, `ksize` must be odd.
+pub fn median_blur<T, I>(src: &I, ksize: u32, border: BorderType) -> Image<T>
+    where T: Pixel,
+          T::Subpixel: MedianSubpixel,
+          I: GenericImage<Pixel=T>
+{
+    assert!(ksize % 2 == 1, "median kernel size {} is not odd", ksize);
+    let (w, h) = src.size();
+    if w == 0 || h == 0 {
+        return Image::new(w, h);
+    }
+    T::Subpixel::median_blur(src, ksize, border)
+}
+
+impl MedianSubpixel for u8 {
+    fn median_blur<T, I>(src: &I, ksize: u32, border: BorderType) -> Image<T>
+        where T: Pixel<Subpixel=u8>,
+              I: GenericImage<Pixel=T>
+    {
+        median_histogram(src, ksize, border)
+    }
+}
+
+macro_rules! median_sorted(
+    ($($t:ty),+) => ($(
+impl MedianSubpixel for $t {
+    fn median_blur<T, I>(src: &I, ksize: u32, border: BorderType) -> Image<T>
+        where T: Pixel<Subpixel=$t>,
+              I: GenericImage<Pixel=T>
+    {
+        median_sort(src, ksize, border)
+    }
+}
+    )+);
+);
+
+median_sorted!(usize, u16, u32, u64, isize, i8, i16, i32, i64, f32, f64);
+
+// Perreault and Hebert, "Median Filtering in Constant Time". Every column
+// of the extended image keeps a histogram of the `ksize` pixels of the
+// current window rows, the window histogram slides along the row by
+// adding and removing column histograms. Histograms have 16 coarse bins
+// on top of the 256 fine ones to speed up the median search.
+fn median_histogram<T, I>(src: &I, ksize: u32, border: BorderType) -> Image<T>
+    where T: Pixel<Subpixel=u8>,
+          I: GenericImage<Pixel=T>
+{
+    let (w, h) = src.size();
+    let channels = T::channels();
+    let cols = border_map(w, ksize, (ksize / 2) as i32, border);
+    let rows = border_map(h, ksize, (ksize / 2) as i32, border);
+    let cval: u8 = border_value(border);
+    let ew = cols.len();
+    let k = ksize as usize;
+    let rank = (k * k / 2) as u32;
+
+    let value = |ey: usize, ex: usize, c: usize| {
+        match (rows[ey], cols[ex]) {
+            (Some(y), Some(x)) => src.row(y as u32)[x].raw()[c],
+            _ => cval
+        }
+    };
+    let update = |fine: &mut [u32], coarse: &mut [u32], v: u8, add: bool| {
+        if add {
+            fine[v as usize] += 1;
+            coarse[v as usize >> 4] += 1;
+        } else {
+            fine[v as usize] -= 1;
+            coarse[v as usize >> 4] -= 1;
+        }
+    };
+
+    let mut col_fine = vec![0u32; ew * channels * 256];
+    let mut col_coarse = vec![0u32; ew * channels * 16];
+    for ey in 0..k {
+        for ex in 0..ew {
+            for c in 0..channels {
+                let i = ex * channels + c;
+                update(&mut col_fine[i * 256..(i + 1) * 256],
+                       &mut col_coarse[i * 16..(i + 1) * 16], value(ey, ex, c), true);
+            }
+        }
+    }
+
+    let mut dst: Image<T> = Image::new(w, h);
+    let mut fine = vec![0u32; channels * 256];
+    let mut coarse = vec![0u32; channels * 16];
+    for y in 0..h as usize {
+        if y > 0 {
+            // slide the column histograms down by one row
+            for ex in 0..ew {
+                for c in 0..channels {
+                    let i = ex * channels + c;
+                    let (f, co) = (&mut col_fine[i * 256..(i + 1) * 256],
+                                   &mut col_coarse[i * 16..(i + 1) * 16]);
+                    update(f, co, value(y - 1, ex, c), false);
+                    update(f, co, value(y + k - 1, ex, c), true);
+                }
+            }
+        }
+
+        for v in fine.iter_mut().chain(coarse.iter_mut()) {
+            *v = 0;
+        }
+        for ex in 0..k {
+            add_histograms(&mut fine, &mut coarse, &col_fine, &col_coarse, ex, channels, true);
+        }
+        let pdst = dst.row_mut(y as u32);
+        for x in 0..w as usize {
+            if x > 0 {
+                add_histograms(&mut fine, &mut coarse, &col_fine, &col_coarse,
+                               x - 1, channels, false);
+                add_histograms(&mut fine, &mut coarse, &col_fine, &col_coarse,
+                               x + k - 1, channels, true);
+            }
+            for c in 0..channels {
+                pdst[x].raw_mut()[c] = histogram_median(&fine[c * 256..(c + 1) * 256],
+                                                        &coarse[c * 16..(c + 1) * 16], rank);
+            }
+        }
+    }
+    dst
+}
+
+#[inline]
+fn add_histograms(fine: &mut [u32], coarse: &mut [u32], col_fine: &[u32], col_coarse: &[u32],
+                  ex: usize, channels: usize, add: bool) {
+    let cf = &col_fine[ex * channels * 256..(ex + 1) * channels * 256];
+    let cc = &col_coarse[ex * channels * 16..(ex + 1) * channels * 16];
+    if add {
+        for (a, b) in fine.iter_mut().zip(cf.iter()) { *a += *b; }
+        for (a, b) in coarse.iter_mut().zip(cc.iter()) { *a += *b; }
+    } else {
+        for (a, b) in fine.iter_mut().zip(cf.iter()) { *a -= *b; }
+        for (a, b) in coarse.iter_mut().zip(cc.iter()) { *a -= *b; }
+    }
+}
+
+// value of 0-based rank `rank` in the histogram
+#[inline]
+fn histogram_median(fine: &[u32], coarse: &[u32], rank: u32) -> u8 {
+    let mut n = 0;
+    let mut bin = 0;
+    while n + coarse[bin] <= rank {
+        n += coarse[bin];
+        bin += 1;
+    }
+    let mut v = bin * 16;
+    while n + fine[v] <= rank {
+        n += fine[v];
+        v += 1;
+    }
+    v as u8
+}
+
+fn median_sort<T, I>(src: &I, ksize: u32, border: BorderType) -> Image<T>
+    where T: Pixel,
+          I: GenericImage<Pixel=T>
+{
+    let (w, h) = src.size();
+    let channels = T::channels();
+    let cols = border_map(w, ksize, (ksize / 2) as i32, border);
+    let rows = border_map(h, ksize, (ksize / 2) as i32, border);
+    let cval: T::Subpixel = border_value(border);
+    let k = ksize as usize;
+
+    let mut dst: Image<T> = Image::new(w, h);
+    for_each_row_mut_with(&mut dst, || Vec::with_capacity(k * k), |window, y, pdst| {
+        let rows = &rows[y as usize..y as usize + k];
+        for x in 0..w as usize {
+            for c in 0..channels {
+                window.clear();
+                for &row in rows.iter() {
+                    for &col in cols[x..x + k].iter() {
+                        window.push(match (row, col) {
+                            (Some(sy), Some(sx)) => src.row(sy as u32)[sx].raw()[c],
+                            _ => cval
+                        });
+                    }
+                }
+                window.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+                pdst[x].raw_mut()[c] = window[k * k / 2];
+            }
+        }
+    });
+    dst
+}
+
+#[cfg(test)]
+mod test {
+    use image::*;
+    use border::{BorderType, all_borders};
+    use super::*;
+
+    #[test]
+    fn test_salt_and_pepper() {
+        let mut img = ImageGray::from_pixel(8, 8, Gray([100]));
+        img[(2, 3)] = Gray([255]);
+        img[(5, 5)] = Gray([0]);
+        img[(0, 0)] = Gray([255]);
+        let out = median_blur(&img, 3, BorderType::Replicate);
+        assert!(out.iter().all(|(_, _, p)| p[0] == 100));
+
+        let mut imgf = ImageGrayf::from_pixel(6, 6, Gray([0.5]));
+        imgf[(3, 3)] = Gray([1.0]);
+        let out = median_blur(&imgf, 3, BorderType::Reflect101);
+        assert!(out.iter().all(|(_, _, p)| p[0] == 0.5));
+
+        // a 1x1 window is the identity
+        let img = ImageGray::from_fn(5, 4, |x, y| Gray([(x * 40 + y) as u8]));
+        let out = median_blur(&img, 1, BorderType::Replicate);
+        assert!(out.iter().zip(img.iter()).all(|(a, b)| a == b));
+    }
+
+    #[test]
+    fn test_histogram_matches_sort() {
+        let img = ImageBgr::from_fn(13, 9, |x, y| {
+            Bgr([((x * 37 + y * 91) % 256) as u8, ((x * y * 13) % 256) as u8,
+                 if (x + y) % 3 == 0 { 255 } else { 10 }])
+        });
+        for &border in all_borders(200.0).iter() {
+            for &k in [3, 5, 7, 11].iter() {
+                let a = median_histogram(&img, k, border);
+                let b = median_sort(&img, k, border);
+                assert!(a.iter().zip(b.iter()).all(|(p, q)| p == q), "{:?} {}", border, k);
+            }
+        }
+    }
+
+    #[test]
+    fn test_median_float() {
+        let img = Image::from_fn(5, 5, |x, y| Gray([(x + 5 * y) as f32]));
+        let out = median_blur(&img, 3, BorderType::Replicate);
+        assert_eq!(out[(2, 2)], Gray([12.0]));
+        assert_eq!(out[(0, 0)], Gray([1.0]));
+        let out = median_blur(&img, 5, BorderType::Constant(-1.0));
+        assert_eq!(out[(0, 0)], Gray([-1.0]));
+        assert_eq!(out[(2, 2)], Gray([12.0]));
+        let img16 = Image::from_fn(3, 3, |x, y| Gray([(x * 1000 + y) as u16]));
+        assert_eq!(median_blur(&img16, 3, BorderType::Wrap)[(1, 1)], Gray([1001]));
+    }
+
+    #[test]
+    fn test_empty_image() {
+        for &(w, h) in [(0, 5), (4, 0), (0, 0)].iter() {
+            for &k in [1, 3, 5].iter() {
+                let img = ImageBgr::new(w, h);
+                assert_eq!(median_blur(&img, k, BorderType::Replicate).size(), (w, h));
+                let imgf = ImageGrayf::new(w, h);
+                assert_eq!(median_blur(&imgf, k, BorderType::Constant(1.0)).size(), (w, h));
+            }
+        }
+    }
+}
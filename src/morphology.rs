@@ -0,0 +1,496 @@
+//! Morphological operations with arbitrary structuring elements.
+//!
+//! Like OpenCV the element is not reflected, pixel (x, y) of the output
+//! is the minimum (erode) or maximum (dilate) of the source pixels
+//! (x + i - anchor.x, y + j - anchor.y) for every set element (i, j).
+
+use image::*;
+use geo::Pointi;
+use traits::Primitive;
+use border::{BorderType, border_map, border_value};
+use par::for_each_row_mut;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MorphShape {
+    Rect,
+    Cross,
+    Ellipse
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MorphOp {
+    Erode,
+    Dilate,
+    /// dilate(erode(src))
+    Open,
+    /// erode(dilate(src))
+    Close,
+    /// dilate(src) - erode(src)
+    Gradient,
+    /// src - open(src)
+    TopHat,
+    /// close(src) - src
+    BlackHat
+}
+
+#[derive(Debug, Clone)]
+pub struct StructuringElement {
+    width: u32,
+    height: u32,
+    anchor: Pointi,
+    mask: Vec<bool>,
+    rect: bool
+}
+
+impl StructuringElement {
+    /// Builds a `width` x `height` element anchored at its center.
+    pub fn new(shape: MorphShape, width: u32, height: u32) -> StructuringElement {
+        assert!(width >= 1 && height >= 1);
+        let (w, h) = (width as usize, height as usize);
+        let (cx, cy) = (w / 2, h / 2);
+        let mut mask = vec![false; w * h];
+        match shape {
+            MorphShape::Rect => {
+                for m in mask.iter_mut() {
+                    *m = true;
+                }
+            },
+            MorphShape::Cross => {
+                for x in 0..w {
+                    mask[cy * w + x] = true;
+                }
+                for y in 0..h {
+                    mask[y * w + cx] = true;
+                }
+            },
+            MorphShape::Ellipse => {
+                let (r, c) = (cx as f32, cy as f32);
+                for y in 0..h {
+                    let dy = y as f32 - c;
+                    let dx = if c > 0.0 {
+                        (r * ((c * c - dy * dy) / (c * c)).max(0.0).sqrt()).round() as usize
+                    } else {
+                        cx
+                    };
+                    let x0 = cx.saturating_sub(dx);
+                    let x1 = (cx + dx + 1).min(w);
+                    for x in x0..x1 {
+                        mask[y * w + x] = true;
+                    }
+                }
+            }
+        }
+        StructuringElement::from_mask_vec(width, height, mask, Pointi::new(cx as i32, cy as i32))
+    }
+
+    /// Builds an element from the non-zero pixels of `mask`, a negative
+    /// anchor coordinate selects the center.
+    pub fn from_mask(mask: &ImageGray, anchor: Pointi) -> StructuringElement {
+        let (w, h) = mask.size();
+        let mut m = Vec::with_capacity((w * h) as usize);
+        for y in 0..h {
+            m.extend(mask.row(y)[..w as usize].iter().map(|p| p[0] != 0));
+        }
+        let anchor = Pointi::new(if anchor.x < 0 { w as i32 / 2 } else { anchor.x },
+                                 if anchor.y < 0 { h as i32 / 2 } else { anchor.y });
+        StructuringElement::from_mask_vec(w, h, m, anchor)
+    }
+
+    fn from_mask_vec(width: u32, height: u32, mask: Vec<bool>, anchor: Pointi)
+        -> StructuringElement {
+        assert!(anchor.x >= 0 && anchor.y >= 0 &&
+                anchor.x < width as i32 && anchor.y < height as i32,
+                "anchor {} outside of the element", anchor);
+        let rect = mask.iter().all(|&m| m);
+        StructuringElement {
+            width: width,
+            height: height,
+            anchor: anchor,
+            mask: mask,
+            rect: rect
+        }
+    }
+
+    /// Moves the anchor, which defaults to the center.
+    pub fn with_anchor(mut self, anchor: Pointi) -> StructuringElement {
+        assert!(anchor.x >= 0 && anchor.y >= 0 &&
+                anchor.x < self.width as i32 && anchor.y < self.height as i32,
+                "anchor {} outside of the element", anchor);
+        self.anchor = anchor;
+        self
+    }
+
+    #[inline]
+    pub fn width(&self) -> u32 { self.width }
+
+    #[inline]
+    pub fn height(&self) -> u32 { self.height }
+
+    #[inline]
+    pub fn anchor(&self) -> Pointi { self.anchor }
+
+    #[inline]
+    pub fn contains(&self, x: u32, y: u32) -> bool {
+        self.mask[(y * self.width + x) as usize]
+    }
+}
+
+#[inline]
+fn min<S: Primitive>(a: S, b: S) -> S {
+    if b < a { b } else { a }
+}
+
+#[inline]
+fn max<S: Primitive>(a: S, b: S) -> S {
+    if b > a { b } else { a }
+}
+
+// van Herk / Gil-Werman: `out[x]` is the extremum of `ext[x..x + k]` for
+// 3 comparisons per pixel whatever `k`, using the running extrema from
+// the start (`g`) and to the end (`h`) of blocks of `k` values
+fn van_herk<S, F>(ext: &[S], k: usize, out: &mut [S], g: &mut Vec<S>, h: &mut Vec<S>, f: F)
+    where S: Primitive,
+          F: Fn(S, S) -> S
+{
+    let n = ext.len();
+    if n == 0 {
+        return;
+    }
+    g.clear();
+    g.extend_from_slice(ext);
+    h.clear();
+    h.extend_from_slice(ext);
+    for i in 1..n {
+        if i % k != 0 {
+            g[i] = f(g[i - 1], ext[i]);
+        }
+    }
+    for i in (0..n - 1).rev() {
+        if (i + 1) % k != 0 {
+            h[i] = f(h[i + 1], ext[i]);
+        }
+    }
+    for x in 0..out.len() {
+        out[x] = f(h[x], g[x + k - 1]);
+    }
+}
+
+// separable pass for rectangular elements
+fn morph_rect<T, I, F>(src: &I, se: &StructuringElement, border: BorderType, f: F) -> Image<T>
+    where T: Pixel,
+          I: GenericImage<Pixel=T>,
+          F: Fn(T::Subpixel, T::Subpixel) -> T::Subpixel + Copy
+{
+    let (w, h) = src.size();
+    let (kw, kh) = (se.width as usize, se.height as usize);
+    let channels = T::channels();
+    let cval: T::Subpixel = border_value(border);
+    let cols = border_map(w, se.width, se.anchor.x, border);
+    let rows = border_map(h, se.height, se.anchor.y, border);
+    let (mut g, mut hb) = (Vec::new(), Vec::new());
+
+    let mut tmp: Image<T> = Image::new(w, h);
+    let mut ext = vec![cval; cols.len().max(rows.len())];
+    let mut out = vec![cval; w.max(h) as usize];
+    for y in 0..h {
+        let psrc = src.row(y);
+        for c in 0..channels {
+            for (e, col) in ext.iter_mut().zip(cols.iter()) {
+                *e = match *col {
+                    Some(x) => psrc[x].raw()[c],
+                    None => cval
+                };
+            }
+            van_herk(&ext[..cols.len()], kw, &mut out[..w as usize], &mut g, &mut hb, f);
+            for (d, v) in tmp.row_mut(y).iter_mut().zip(out.iter()) {
+                d.raw_mut()[c] = *v;
+            }
+        }
+    }
+
+    let mut dst: Image<T> = Image::new(w, h);
+    for x in 0..w as usize {
+        for c in 0..channels {
+            for (e, row) in ext.iter_mut().zip(rows.iter()) {
+                *e = match *row {
+                    Some(y) => tmp.row(y as u32)[x].raw()[c],
+                    None => cval
+                };
+            }
+            van_herk(&ext[..rows.len()], kh, &mut out[..h as usize], &mut g, &mut hb, f);
+            for y in 0..h as usize {
+                dst.row_mut(y as u32)[x].raw_mut()[c] = out[y];
+            }
+        }
+    }
+    dst
+}
+
+fn morph_mask<T, I, F>(src: &I, se: &StructuringElement, border: BorderType, f: F) -> Image<T>
+    where T: Pixel,
+          I: GenericImage<Pixel=T>,
+          F: Fn(T::Subpixel, T::Subpixel) -> T::Subpixel + Sync + Send
+{
+    let (w, h) = src.size();
+    let channels = T::channels();
+    let cval: T::Subpixel = border_value(border);
+    let cols = border_map(w, se.width, se.anchor.x, border);
+    let rows = border_map(h, se.height, se.anchor.y, border);
+    let mut taps = Vec::new();
+    for j in 0..se.height {
+        for i in 0..se.width {
+            if se.contains(i, j) {
+                taps.push((i as usize, j as usize));
+            }
+        }
+    }
+
+    let mut dst: Image<T> = Image::new(w, h);
+    for_each_row_mut(&mut dst, |y, pdst| {
+        for x in 0..w as usize {
+            for c in 0..channels {
+                let mut acc: Option<T::Subpixel> = None;
+                for &(i, j) in taps.iter() {
+                    let v = match (rows[y as usize + j], cols[x + i]) {
+                        (Some(sy), Some(sx)) => src.row(sy as u32)[sx].raw()[c],
+                        _ => cval
+                    };
+                    acc = Some(match acc {
+                        Some(a) => f(a, v),
+                        None => v
+                    });
+                }
+                // an empty element leaves the image unchanged
+                pdst[x].raw_mut()[c] = match acc {
+                    Some(a) => a,
+                    None => src.row(y)[x].raw()[c]
+                };
+            }
+        }
+    });
+    dst
+}
+
+fn morph_once<T, I, F>(src: &I, se: &StructuringElement, border: BorderType, f: F) -> Image<T>
+    where T: Pixel,
+          I: GenericImage<Pixel=T>,
+          F: Fn(T::Subpixel, T::Subpixel) -> T::Subpixel + Copy + Sync + Send
+{
+    let (w, h) = src.size();
+    if w == 0 || h == 0 {
+        Image::new(w, h)
+    } else if se.rect {
+        morph_rect(src, se, border, f)
+    } else {
+        morph_mask(src, se, border, f)
+    }
+}
+
+fn morph<T, I, F>(src: &I, se: &StructuringElement, iterations: u32, border: BorderType,
+                  f: F) -> Image<T>
+    where T: Pixel,
+          I: GenericImage<Pixel=T>,
+          F: Fn(T::Subpixel, T::Subpixel) -> T::Subpixel + Copy + Sync + Send
+{
+    if iterations == 0 {
+        return src.to_image();
+    }
+    let mut dst = morph_once(src, se, border, f);
+    for _ in 1..iterations {
+        dst = morph_once(&dst, se, border, f);
+    }
+    dst
+}
+
+/// Minimum over the element, applied `iterations` times.
+pub fn erode<T, I>(src: &I, se: &StructuringElement, iterations: u32,
+                   border: BorderType) -> Image<T>
+    where T: Pixel,
+          I: GenericImage<Pixel=T>
+{
+    morph(src, se, iterations, border, min)
+}
+
+/// Maximum over the element, applied `iterations` times.
+pub fn dilate<T, I>(src: &I, se: &StructuringElement, iterations: u32,
+                    border: BorderType) -> Image<T>
+    where T: Pixel,
+          I: GenericImage<Pixel=T>
+{
+    morph(src, se, iterations, border, max)
+}
+
+#[inline]
+fn saturating_sub<S: Primitive>(a: S, b: S) -> S {
+    if a > b { a - b } else { S::zero() }
+}
+
+// a - b per channel, clamped at zero
+fn difference<T: Pixel>(a: &Image<T>, b: &Image<T>) -> Image<T> {
+    let mut dst: Image<T> = Image::new(a.width(), a.height());
+    for_each_row_mut(&mut dst, |y, pdst| {
+        for ((d, p), q) in pdst.iter_mut().zip(a.row(y).iter()).zip(b.row(y).iter()) {
+            for c in 0..T::channels() {
+                d.raw_mut()[c] = saturating_sub(p.raw()[c], q.raw()[c]);
+            }
+        }
+    });
+    dst
+}
+
+/// Erosion, dilation and the operations derived from them, each erosion
+/// and dilation being applied `iterations` times.
+pub fn morphology_ex<T, I>(src: &I, op: MorphOp, se: &StructuringElement, iterations: u32,
+                           border: BorderType) -> Image<T>
+    where T: Pixel,
+          I: GenericImage<Pixel=T>
+{
+    match op {
+        MorphOp::Erode => erode(src, se, iterations, border),
+        MorphOp::Dilate => dilate(src, se, iterations, border),
+        MorphOp::Open => dilate(&erode(src, se, iterations, border), se, iterations, border),
+        MorphOp::Close => erode(&dilate(src, se, iterations, border), se, iterations, border),
+        MorphOp::Gradient => {
+            difference(&dilate(src, se, iterations, border), &erode(src, se, iterations, border))
+        },
+        MorphOp::TopHat => {
+            let open = morphology_ex(src, MorphOp::Open, se, iterations, border);
+            difference(&src.to_image(), &open)
+        },
+        MorphOp::BlackHat => {
+            let close = morphology_ex(src, MorphOp::Close, se, iterations, border);
+            difference(&close, &src.to_image())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use image::*;
+    use geo::Pointi;
+    use border::{BorderType, all_borders};
+    use super::*;
+
+    fn mask(se: &StructuringElement) -> Vec<Vec<u8>> {
+        (0..se.height()).map(|y| {
+            (0..se.width()).map(|x| se.contains(x, y) as u8).collect()
+        }).collect()
+    }
+
+    #[test]
+    fn test_structuring_element() {
+        let e = StructuringElement::new(MorphShape::Ellipse, 5, 5);
+        assert_eq!(mask(&e), vec![vec![0, 0, 1, 0, 0],
+                                  vec![1, 1, 1, 1, 1],
+                                  vec![1, 1, 1, 1, 1],
+                                  vec![1, 1, 1, 1, 1],
+                                  vec![0, 0, 1, 0, 0]]);
+        let c = StructuringElement::new(MorphShape::Cross, 3, 5);
+        assert_eq!(mask(&c), vec![vec![0, 1, 0], vec![0, 1, 0], vec![1, 1, 1],
+                                  vec![0, 1, 0], vec![0, 1, 0]]);
+        assert_eq!(c.anchor(), Pointi::new(1, 2));
+        assert!(StructuringElement::new(MorphShape::Rect, 4, 2).rect);
+        assert!(!c.rect);
+
+        let m = ImageGray::from_fn(3, 2, |x, y| Gray([(x == y) as u8 * 9]));
+        let s = StructuringElement::from_mask(&m, Pointi::new(-1, 0));
+        assert_eq!(mask(&s), vec![vec![1, 0, 0], vec![0, 1, 0]]);
+        assert_eq!(s.anchor(), Pointi::new(1, 0));
+    }
+
+    #[test]
+    fn test_erode_dilate() {
+        let mut img = ImageGray::from_pixel(7, 7, Gray([0]));
+        img[(3, 3)] = Gray([255]);
+        let cross = StructuringElement::new(MorphShape::Cross, 3, 3);
+        let out = dilate(&img, &cross, 1, BorderType::Replicate);
+        let on: Vec<(u32, u32)> = out.iter().filter(|&(_, _, p)| p[0] == 255)
+            .map(|(x, y, _)| (x, y)).collect();
+        assert_eq!(on, vec![(3, 2), (2, 3), (3, 3), (4, 3), (3, 4)]);
+        let out = dilate(&img, &cross, 2, BorderType::Replicate);
+        assert_eq!(out.iter().filter(|&(_, _, p)| p[0] == 255).count(), 13);
+        assert!(erode(&out, &cross, 2, BorderType::Replicate).iter()
+                .zip(img.iter()).all(|(a, b)| a == b));
+
+        // a corner anchor shifts the result
+        let rect = StructuringElement::new(MorphShape::Rect, 2, 2).with_anchor(Pointi::new(0, 0));
+        let out = dilate(&img, &rect, 1, BorderType::Replicate);
+        let on: Vec<(u32, u32)> = out.iter().filter(|&(_, _, p)| p[0] == 255)
+            .map(|(x, y, _)| (x, y)).collect();
+        assert_eq!(on, vec![(2, 2), (3, 2), (2, 3), (3, 3)]);
+
+        // the border value only matters when it is extreme
+        let flat = ImageGrayf::from_pixel(4, 4, Gray([0.5]));
+        let rect = StructuringElement::new(MorphShape::Rect, 3, 3);
+        assert_eq!(erode(&flat, &rect, 1, BorderType::Constant(0.0))[(0, 2)], Gray([0.0]));
+        assert_eq!(erode(&flat, &rect, 1, BorderType::Constant(1.0))[(0, 2)], Gray([0.5]));
+        assert_eq!(erode(&flat, &rect, 0, BorderType::Constant(0.0))[(0, 2)], Gray([0.5]));
+    }
+
+    #[test]
+    fn test_van_herk_matches_mask() {
+        let img = ImageBgr::from_fn(11, 9, |x, y| {
+            Bgr([((x * 37 + y * 91) % 256) as u8, ((x * y * 13) % 256) as u8, (x * 20) as u8])
+        });
+        for &border in all_borders(77.0).iter() {
+            for &(w, h, ax, ay) in [(3, 3, 1, 1), (4, 2, 0, 1), (1, 5, 0, 4), (6, 7, 5, 2)].iter() {
+                let se = StructuringElement::new(MorphShape::Rect, w, h)
+                    .with_anchor(Pointi::new(ax, ay));
+                let a = morph_rect(&img, &se, border, min);
+                let b = morph_mask(&img, &se, border, min);
+                assert!(a.iter().zip(b.iter()).all(|(p, q)| p == q), "{:?} {}x{}", border, w, h);
+                let a = morph_rect(&img, &se, border, max);
+                let b = morph_mask(&img, &se, border, max);
+                assert!(a.iter().zip(b.iter()).all(|(p, q)| p == q), "{:?} {}x{}", border, w, h);
+            }
+        }
+    }
+
+    #[test]
+    fn test_morphology_ex() {
+        // a 4x4 square, a speck and a hole
+        let mut img = ImageGray::from_fn(12, 12, |x, y| {
+            Gray([if x >= 2 && x < 6 && y >= 2 && y < 6 { 200 } else { 0 }])
+        });
+        img[(9, 9)] = Gray([200]);
+        img[(3, 3)] = Gray([0]);
+        let se = StructuringElement::new(MorphShape::Rect, 3, 3);
+        let b = BorderType::Replicate;
+
+        let open = morphology_ex(&img, MorphOp::Open, &se, 1, b);
+        assert_eq!(open[(9, 9)], Gray([0]));
+        let close = morphology_ex(&img, MorphOp::Close, &se, 1, b);
+        assert_eq!(close[(3, 3)], Gray([200]));
+        assert_eq!(close[(9, 9)], Gray([200]));
+
+        let top_hat = morphology_ex(&img, MorphOp::TopHat, &se, 1, b);
+        assert_eq!(top_hat[(9, 9)], Gray([200]));
+        assert_eq!(top_hat[(0, 0)], Gray([0]));
+        let black_hat = morphology_ex(&img, MorphOp::BlackHat, &se, 1, b);
+        assert_eq!(black_hat[(3, 3)], Gray([200]));
+        assert_eq!(black_hat[(9, 9)], Gray([0]));
+
+        let square = ImageGray::from_fn(8, 8, |x, y| {
+            Gray([if x >= 2 && x < 6 && y >= 2 && y < 6 { 100 } else { 0 }])
+        });
+        let grad = morphology_ex(&square, MorphOp::Gradient, &se, 1, b);
+        assert_eq!(grad.row(4), &[Gray([0]), Gray([100]), Gray([100]), Gray([0]),
+                                  Gray([0]), Gray([100]), Gray([100]), Gray([0])]);
+    }
+
+    #[test]
+    fn test_empty_image() {
+        let tall = StructuringElement::new(MorphShape::Rect, 1, 3);
+        let wide = StructuringElement::new(MorphShape::Rect, 3, 1);
+        let cross = StructuringElement::new(MorphShape::Cross, 3, 3);
+        for &(w, h) in [(0, 5), (4, 0), (0, 0)].iter() {
+            let img = ImageGray::new(w, h);
+            for se in [&tall, &wide, &cross].iter() {
+                assert_eq!(erode(&img, se, 1, BorderType::Replicate).size(), (w, h));
+                assert_eq!(dilate(&img, se, 2, BorderType::Constant(0.0)).size(), (w, h));
+            }
+        }
+        let (mut g, mut h) = (Vec::new(), Vec::new());
+        van_herk::<u8, _>(&[], 1, &mut [], &mut g, &mut h, min);
+    }
+}
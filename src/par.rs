@@ -0,0 +1,56 @@
+//! Row dispatch for the heavy image routines. Rows are processed on the
+//! rayon thread pool when the `parallel` feature is enabled and serially
+//! otherwise, with identical results. Only the parallel build needs the
+//! closures and the images they read to be `Send` and `Sync`.
+
+use image::{Image, Pixel};
+
+/// Calls `f(y, row)` for every row of `dst`. Images without columns have
+/// no rows to process.
+#[cfg(not(feature = "parallel"))]
+pub fn for_each_row_mut<T, F>(dst: &mut Image<T>, f: F)
+    where T: Pixel,
+          F: Fn(u32, &mut [T])
+{
+    for_each_row_mut_with(dst, || (), |_, y, row| f(y, row))
+}
+
+#[cfg(feature = "parallel")]
+pub fn for_each_row_mut<T, F>(dst: &mut Image<T>, f: F)
+    where T: Pixel,
+          F: Fn(u32, &mut [T]) + Sync + Send
+{
+    for_each_row_mut_with(dst, || (), |_, y, row| f(y, row))
+}
+
+/// Like `for_each_row_mut`, with a scratch value created by `init` and
+/// reused across the rows handled by the same thread.
+#[cfg(not(feature = "parallel"))]
+pub fn for_each_row_mut_with<T, S, I, F>(dst: &mut Image<T>, init: I, f: F)
+    where T: Pixel,
+          I: Fn() -> S,
+          F: Fn(&mut S, u32, &mut [T])
+{
+    if dst.width() == 0 {
+        return;
+    }
+    let mut scratch = init();
+    for y in 0..dst.height() {
+        f(&mut scratch, y, dst.row_mut(y));
+    }
+}
+
+#[cfg(feature = "parallel")]
+pub fn for_each_row_mut_with<T, S, I, F>(dst: &mut Image<T>, init: I, f: F)
+    where T: Pixel,
+          S: Send,
+          I: Fn() -> S + Sync + Send,
+          F: Fn(&mut S, u32, &mut [T]) + Sync + Send
+{
+    use rayon::prelude::*;
+    if dst.width() == 0 {
+        return;
+    }
+    dst.par_rows_mut().enumerate().for_each_init(init,
+        |scratch, (y, row)| f(scratch, y as u32, row));
+}
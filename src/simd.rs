@@ -0,0 +1,244 @@
+//! Vectorized row kernels for the built-in color mappers. Every kernel
+//! processes as many pixels as it can in full vectors and leaves the tail
+//! to the scalar `ColorMapper::to`, so the output is bit-identical to the
+//! generic path.
+
+use image::{Bgra, Gray};
+use convert::{ColorMapper, MapBgraGray, MapGrayBgra};
+
+#[cfg(target_arch = "x86")]
+use std::arch::x86::*;
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+
+pub fn bgra_to_gray(src: &[Bgra<u8>], dst: &mut [Gray<u8>]) {
+    let n = src.len().min(dst.len());
+    let (src, dst) = (&src[..n], &mut dst[..n]);
+    let done = bgra_to_gray_fast(src, dst);
+    for (d, s) in dst[done..].iter_mut().zip(src[done..].iter()) {
+        *d = MapBgraGray::to(s);
+    }
+}
+
+pub fn gray_to_bgra(src: &[Gray<u8>], dst: &mut [Bgra<u8>]) {
+    let n = src.len().min(dst.len());
+    let (src, dst) = (&src[..n], &mut dst[..n]);
+    let done = gray_to_bgra_fast(src, dst);
+    for (d, s) in dst[done..].iter_mut().zip(src[done..].iter()) {
+        *d = MapGrayBgra::to(s);
+    }
+}
+
+// returns the number of pixels converted
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn bgra_to_gray_fast(src: &[Bgra<u8>], dst: &mut [Gray<u8>]) -> usize {
+    if is_x86_feature_detected!("avx2") {
+        unsafe { bgra_to_gray_avx2(src, dst) }
+    } else if is_x86_feature_detected!("sse2") {
+        unsafe { bgra_to_gray_sse2(src, dst) }
+    } else {
+        0
+    }
+}
+
+#[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+fn bgra_to_gray_fast(_src: &[Bgra<u8>], _dst: &mut [Gray<u8>]) -> usize {
+    0
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn gray_to_bgra_fast(src: &[Gray<u8>], dst: &mut [Bgra<u8>]) -> usize {
+    if is_x86_feature_detected!("avx2") {
+        unsafe { gray_to_bgra_avx2(src, dst) }
+    } else if is_x86_feature_detected!("sse2") {
+        unsafe { gray_to_bgra_sse2(src, dst) }
+    } else {
+        0
+    }
+}
+
+#[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+fn gray_to_bgra_fast(_src: &[Gray<u8>], _dst: &mut [Bgra<u8>]) -> usize {
+    0
+}
+
+// (b * 28 + g * 151 + r * 77 + 128) >> 8 of 4 BGRA pixels, as 4 x i32
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[inline(always)]
+unsafe fn gray4_sse2(v: __m128i, weights: __m128i) -> __m128i {
+    let zero = _mm_setzero_si128();
+    // [b*28 + g*151, r*77] per pixel
+    let lo = _mm_madd_epi16(_mm_unpacklo_epi8(v, zero), weights);
+    let hi = _mm_madd_epi16(_mm_unpackhi_epi8(v, zero), weights);
+    let lo = _mm_add_epi32(lo, _mm_srli_epi64(lo, 32));
+    let hi = _mm_add_epi32(hi, _mm_srli_epi64(hi, 32));
+    let sum = _mm_unpacklo_epi64(_mm_shuffle_epi32(lo, 0b00_00_10_00),
+                                 _mm_shuffle_epi32(hi, 0b00_00_10_00));
+    _mm_srli_epi32(_mm_add_epi32(sum, _mm_set1_epi32(128)), 8)
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "sse2")]
+unsafe fn bgra_to_gray_sse2(src: &[Bgra<u8>], dst: &mut [Gray<u8>]) -> usize {
+    let weights = _mm_setr_epi16(28, 151, 77, 0, 28, 151, 77, 0);
+    let n = src.len() / 16 * 16;
+    let psrc = src.as_ptr() as *const __m128i;
+    let pdst = dst.as_mut_ptr() as *mut __m128i;
+    for i in 0..n / 16 {
+        let a = gray4_sse2(_mm_loadu_si128(psrc.offset(4 * i as isize)), weights);
+        let b = gray4_sse2(_mm_loadu_si128(psrc.offset(4 * i as isize + 1)), weights);
+        let c = gray4_sse2(_mm_loadu_si128(psrc.offset(4 * i as isize + 2)), weights);
+        let d = gray4_sse2(_mm_loadu_si128(psrc.offset(4 * i as isize + 3)), weights);
+        let out = _mm_packus_epi16(_mm_packs_epi32(a, b), _mm_packs_epi32(c, d));
+        _mm_storeu_si128(pdst.offset(i as isize), out);
+    }
+    n
+}
+
+// same as `gray4_sse2` on 8 pixels, the two 128-bit lanes hold pixels 0-3
+// and 4-7 so the result is in order
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[inline(always)]
+unsafe fn gray8_avx2(v: __m256i, weights: __m256i) -> __m256i {
+    let zero = _mm256_setzero_si256();
+    let lo = _mm256_madd_epi16(_mm256_unpacklo_epi8(v, zero), weights);
+    let hi = _mm256_madd_epi16(_mm256_unpackhi_epi8(v, zero), weights);
+    let lo = _mm256_add_epi32(lo, _mm256_srli_epi64(lo, 32));
+    let hi = _mm256_add_epi32(hi, _mm256_srli_epi64(hi, 32));
+    let sum = _mm256_unpacklo_epi64(_mm256_shuffle_epi32(lo, 0b00_00_10_00),
+                                    _mm256_shuffle_epi32(hi, 0b00_00_10_00));
+    _mm256_srli_epi32(_mm256_add_epi32(sum, _mm256_set1_epi32(128)), 8)
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "avx2")]
+unsafe fn bgra_to_gray_avx2(src: &[Bgra<u8>], dst: &mut [Gray<u8>]) -> usize {
+    let weights = _mm256_setr_epi16(28, 151, 77, 0, 28, 151, 77, 0,
+                                    28, 151, 77, 0, 28, 151, 77, 0);
+    // packing works per lane, this restores the dword order
+    let order = _mm256_setr_epi32(0, 4, 1, 5, 2, 6, 3, 7);
+    let n = src.len() / 32 * 32;
+    let psrc = src.as_ptr() as *const __m256i;
+    let pdst = dst.as_mut_ptr() as *mut __m256i;
+    for i in 0..n / 32 {
+        let a = gray8_avx2(_mm256_loadu_si256(psrc.offset(4 * i as isize)), weights);
+        let b = gray8_avx2(_mm256_loadu_si256(psrc.offset(4 * i as isize + 1)), weights);
+        let c = gray8_avx2(_mm256_loadu_si256(psrc.offset(4 * i as isize + 2)), weights);
+        let d = gray8_avx2(_mm256_loadu_si256(psrc.offset(4 * i as isize + 3)), weights);
+        let out = _mm256_packus_epi16(_mm256_packs_epi32(a, b), _mm256_packs_epi32(c, d));
+        _mm256_storeu_si256(pdst.offset(i as isize),
+                            _mm256_permutevar8x32_epi32(out, order));
+    }
+    n
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "sse2")]
+unsafe fn gray_to_bgra_sse2(src: &[Gray<u8>], dst: &mut [Bgra<u8>]) -> usize {
+    let alpha = _mm_set1_epi32(0xff000000u32 as i32);
+    let n = src.len() / 16 * 16;
+    let psrc = src.as_ptr() as *const __m128i;
+    let pdst = dst.as_mut_ptr() as *mut __m128i;
+    for i in 0..n / 16 {
+        let v = _mm_loadu_si128(psrc.offset(i as isize));
+        let lo = _mm_unpacklo_epi8(v, v);
+        let hi = _mm_unpackhi_epi8(v, v);
+        let out = pdst.offset(4 * i as isize);
+        _mm_storeu_si128(out, _mm_or_si128(_mm_unpacklo_epi16(lo, lo), alpha));
+        _mm_storeu_si128(out.offset(1), _mm_or_si128(_mm_unpackhi_epi16(lo, lo), alpha));
+        _mm_storeu_si128(out.offset(2), _mm_or_si128(_mm_unpacklo_epi16(hi, hi), alpha));
+        _mm_storeu_si128(out.offset(3), _mm_or_si128(_mm_unpackhi_epi16(hi, hi), alpha));
+    }
+    n
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "avx2")]
+unsafe fn gray_to_bgra_avx2(src: &[Gray<u8>], dst: &mut [Bgra<u8>]) -> usize {
+    let alpha = _mm256_set1_epi32(0xff000000u32 as i32);
+    let spread = _mm256_set1_epi32(0x00010101);
+    let n = src.len() / 8 * 8;
+    let psrc = src.as_ptr() as *const u8;
+    let pdst = dst.as_mut_ptr() as *mut __m256i;
+    for i in 0..n / 8 {
+        let v = _mm_loadl_epi64(psrc.offset(8 * i as isize) as *const __m128i);
+        let g = _mm256_mullo_epi32(_mm256_cvtepu8_epi32(v), spread);
+        _mm256_storeu_si256(pdst.offset(i as isize), _mm256_or_si256(g, alpha));
+    }
+    n
+}
+
+#[cfg(test)]
+mod test {
+    use image::*;
+    use convert::{ColorMapper, MapBgraGray, MapGrayBgra};
+    use super::*;
+
+    fn bgra_row(n: usize) -> Vec<Bgra<u8>> {
+        (0..n).map(|i| {
+            let i = i * 7919;
+            Bgra([i as u8, (i >> 3) as u8, (i >> 6) as u8, (i >> 9) as u8])
+        }).collect()
+    }
+
+    fn check_gray(src: &[Bgra<u8>], dst: &[Gray<u8>], done: usize) {
+        for i in 0..done {
+            assert_eq!(dst[i], MapBgraGray::to(&src[i]), "pixel {}", i);
+        }
+    }
+
+    fn check_bgra(src: &[Gray<u8>], dst: &[Bgra<u8>], done: usize) {
+        for i in 0..done {
+            assert_eq!(dst[i], MapGrayBgra::to(&src[i]), "pixel {}", i);
+        }
+    }
+
+    #[test]
+    fn test_bgra_to_gray() {
+        let mut src = bgra_row(1027);
+        src[0] = Bgra([255, 255, 255, 255]);
+        let mut dst = vec![Gray([0]); src.len()];
+        bgra_to_gray(&src, &mut dst);
+        check_gray(&src, &dst, src.len());
+
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        {
+            if is_x86_feature_detected!("sse2") {
+                let mut dst = vec![Gray([0]); src.len()];
+                let done = unsafe { bgra_to_gray_sse2(&src, &mut dst) };
+                assert_eq!(done, 1024);
+                check_gray(&src, &dst, done);
+            }
+            if is_x86_feature_detected!("avx2") {
+                let mut dst = vec![Gray([0]); src.len()];
+                let done = unsafe { bgra_to_gray_avx2(&src, &mut dst) };
+                assert_eq!(done, 1024);
+                check_gray(&src, &dst, done);
+            }
+        }
+    }
+
+    #[test]
+    fn test_gray_to_bgra() {
+        let src: Vec<Gray<u8>> = (0..1027).map(|i| Gray([(i * 31) as u8])).collect();
+        let mut dst = vec![Bgra([0, 0, 0, 0]); src.len()];
+        gray_to_bgra(&src, &mut dst);
+        check_bgra(&src, &dst, src.len());
+
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        {
+            if is_x86_feature_detected!("sse2") {
+                let mut dst = vec![Bgra([0, 0, 0, 0]); src.len()];
+                let done = unsafe { gray_to_bgra_sse2(&src, &mut dst) };
+                assert_eq!(done, 1024);
+                check_bgra(&src, &dst, done);
+            }
+            if is_x86_feature_detected!("avx2") {
+                let mut dst = vec![Bgra([0, 0, 0, 0]); src.len()];
+                let done = unsafe { gray_to_bgra_avx2(&src, &mut dst) };
+                assert_eq!(done, 1024);
+                check_bgra(&src, &dst, done);
+            }
+        }
+    }
+}
@@ -0,0 +1,263 @@
+//! Fixed, automatic and adaptive thresholding of gray images.
+
+use image::*;
+use num::traits::ToPrimitive;
+use traits::{Primitive, SaturateCast};
+use border::BorderType;
+use conv::gaussian_blur_with_border;
+use integral::blur;
+use par::for_each_row_mut;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThresholdType {
+    /// `maxval` above the threshold, 0 otherwise
+    Binary,
+    /// 0 above the threshold, `maxval` otherwise
+    BinaryInv,
+    /// the threshold above it, unchanged otherwise
+    Trunc,
+    /// unchanged above the threshold, 0 otherwise
+    ToZero,
+    /// 0 above the threshold, unchanged otherwise
+    ToZeroInv
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdaptiveMethod {
+    /// mean of the block
+    Mean,
+    /// gaussian weighted mean of the block
+    Gaussian
+}
+
+#[inline]
+fn apply(v: f32, thresh: f32, maxval: f32, ty: ThresholdType) -> f32 {
+    let above = v > thresh;
+    match ty {
+        ThresholdType::Binary => if above { maxval } else { 0.0 },
+        ThresholdType::BinaryInv => if above { 0.0 } else { maxval },
+        ThresholdType::Trunc => if above { thresh } else { v },
+        ThresholdType::ToZero => if above { v } else { 0.0 },
+        ThresholdType::ToZeroInv => if above { 0.0 } else { v }
+    }
+}
+
+/// Compares every pixel to `thresh` as described by `ty`, the written
+/// values saturate to the pixel type.
+pub fn threshold<S, I>(src: &I, thresh: f32, maxval: f32, ty: ThresholdType) -> Image<Gray<S>>
+    where S: Primitive,
+          I: GenericImage<Pixel=Gray<S>>
+{
+    let mut dst: Image<Gray<S>> = Image::new(src.width(), src.height());
+    for_each_row_mut(&mut dst, |y, pdst| {
+        for (d, s) in pdst.iter_mut().zip(src.row(y).iter()) {
+            let v = s[0].to_f32().unwrap();
+            *d = Gray([SaturateCast::saturate_cast(apply(v, thresh, maxval, ty))]);
+        }
+    });
+    dst
+}
+
+fn histogram<I>(src: &I) -> [u32; 256]
+    where I: GenericImage<Pixel=Gray<u8>>
+{
+    let mut hist = [0u32; 256];
+    for y in 0..src.height() {
+        for p in src.row(y)[..src.width() as usize].iter() {
+            hist[p[0] as usize] += 1;
+        }
+    }
+    hist
+}
+
+/// Otsu's threshold, the value maximizing the between-class variance of
+/// the pixels at or below it and of those above it.
+pub fn otsu_threshold<I>(src: &I) -> u8
+    where I: GenericImage<Pixel=Gray<u8>>
+{
+    let hist = histogram(src);
+    let n = (src.width() * src.height()) as f64;
+    let mu = hist.iter().enumerate().fold(0f64, |s, (i, &h)| s + i as f64 * h as f64) / n;
+    let (mut q1, mut s1) = (0f64, 0f64);
+    let (mut max_sigma, mut best) = (0f64, 0u8);
+    for i in 0..256 {
+        let p = hist[i] as f64 / n;
+        q1 += p;
+        s1 += i as f64 * p;
+        let q2 = 1.0 - q1;
+        if q1.min(q2) < 1e-7 {
+            continue;
+        }
+        let mu1 = s1 / q1;
+        let mu2 = (mu - s1) / q2;
+        let sigma = q1 * q2 * (mu1 - mu2) * (mu1 - mu2);
+        if sigma > max_sigma {
+            max_sigma = sigma;
+            best = i as u8;
+        }
+    }
+    best
+}
+
+/// Zack's triangle threshold, suited to histograms with a single peak and
+/// a long tail: the level of the tail farthest from the line joining the
+/// peak to the end of the tail.
+pub fn triangle_threshold<I>(src: &I) -> u8
+    where I: GenericImage<Pixel=Gray<u8>>
+{
+    let mut hist = histogram(src);
+    let left = match hist.iter().position(|&h| h > 0) {
+        Some(i) => i,
+        None => return 0
+    };
+    let right = hist.iter().rposition(|&h| h > 0).unwrap();
+    let mut peak = 0;
+    for i in left..right + 1 {
+        if hist[i] > hist[peak] {
+            peak = i;
+        }
+    }
+    let mut left = if left > 0 { left - 1 } else { left };
+    let right = if right < 255 { right + 1 } else { right };
+
+    // walk the longer tail, which is made the left one
+    let flipped = peak - left < right - peak;
+    if flipped {
+        hist.reverse();
+        left = 255 - right;
+        peak = 255 - peak;
+    }
+    let (a, b) = (hist[peak] as f64, left as f64 - peak as f64);
+    let (mut thresh, mut dist) = (left, 0f64);
+    for i in left + 1..peak + 1 {
+        let d = a * i as f64 + b * hist[i] as f64;
+        if d > dist {
+            dist = d;
+            thresh = i;
+        }
+    }
+    let thresh = if thresh > 0 { thresh - 1 } else { 0 };
+    if flipped { (255 - thresh) as u8 } else { thresh as u8 }
+}
+
+/// Thresholds every pixel against the mean of its `block_size` x
+/// `block_size` neighborhood minus `c`. Only `Binary` and `BinaryInv`
+/// are meaningful for `ty`.
+pub fn adaptive_threshold<S, I>(src: &I, maxval: f32, method: AdaptiveMethod, ty: ThresholdType,
+                                block_size: u32, c: f32, border: BorderType) -> Image<Gray<S>>
+    where S: Primitive,
+          I: GenericImage<Pixel=Gray<S>>
+{
+    assert!(block_size % 2 == 1 && block_size > 1,
+            "adaptive threshold block size {} is not odd", block_size);
+    assert!(ty == ThresholdType::Binary || ty == ThresholdType::BinaryInv,
+            "adaptive threshold only supports binary thresholds");
+    let mean: Image<Gray<S>> = match method {
+        AdaptiveMethod::Mean => blur(src, block_size, block_size, border),
+        AdaptiveMethod::Gaussian => gaussian_blur_with_border(src, block_size as usize, 0.0, border)
+    };
+    let mut dst: Image<Gray<S>> = Image::new(src.width(), src.height());
+    for_each_row_mut(&mut dst, |y, pdst| {
+        for ((d, s), m) in pdst.iter_mut().zip(src.row(y).iter()).zip(mean.row(y).iter()) {
+            let (v, t) = (s[0].to_f32().unwrap(), m[0].to_f32().unwrap() - c);
+            *d = Gray([SaturateCast::saturate_cast(apply(v, t, maxval, ty))]);
+        }
+    });
+    dst
+}
+
+#[cfg(test)]
+mod test {
+    use image::*;
+    use border::BorderType;
+    use super::*;
+
+    fn values<S: Primitive>(img: &Image<Gray<S>>) -> Vec<S> {
+        img.iter().map(|(_, _, p)| p[0]).collect()
+    }
+
+    #[test]
+    fn test_threshold() {
+        let img = ImageGray::from_fn(5, 1, |x, _| Gray([(x * 60) as u8]));
+        assert_eq!(values(&threshold(&img, 100.0, 255.0, ThresholdType::Binary)),
+                   vec![0, 0, 255, 255, 255]);
+        assert_eq!(values(&threshold(&img, 120.0, 7.0, ThresholdType::BinaryInv)),
+                   vec![7, 7, 7, 0, 0]);
+        assert_eq!(values(&threshold(&img, 100.0, 0.0, ThresholdType::Trunc)),
+                   vec![0, 60, 100, 100, 100]);
+        assert_eq!(values(&threshold(&img, 100.0, 0.0, ThresholdType::ToZero)),
+                   vec![0, 0, 120, 180, 240]);
+        assert_eq!(values(&threshold(&img, 100.0, 0.0, ThresholdType::ToZeroInv)),
+                   vec![0, 60, 0, 0, 0]);
+        // maxval saturates
+        assert_eq!(values(&threshold(&img, 100.0, 1000.0, ThresholdType::Binary))[4], 255);
+
+        let imgf = ImageGrayf::from_fn(4, 1, |x, _| Gray([x as f32 * 0.25]));
+        assert_eq!(values(&threshold(&imgf, 0.3, 1.0, ThresholdType::Binary)),
+                   vec![0.0, 0.0, 1.0, 1.0]);
+        assert_eq!(values(&threshold(&imgf, 0.3, 1.0, ThresholdType::Trunc)),
+                   vec![0.0, 0.25, 0.3, 0.3]);
+    }
+
+    #[test]
+    fn test_otsu() {
+        // two noisy classes around 40 and 200
+        let img = ImageGray::from_fn(20, 10, |x, y| {
+            let noise = ((x * 7 + y * 3) % 11) as u8;
+            Gray([if x < 8 { 35 + noise } else { 195 + noise }])
+        });
+        let t = otsu_threshold(&img);
+        assert!(t >= 45 && t < 195, "{}", t);
+        let out = threshold(&img, t as f32, 255.0, ThresholdType::Binary);
+        assert!(out.iter().all(|(x, _, p)| p[0] == if x < 8 { 0 } else { 255 }));
+
+        let flat = ImageGray::from_pixel(4, 4, Gray([9]));
+        assert_eq!(otsu_threshold(&flat), 0);
+    }
+
+    #[test]
+    fn test_triangle() {
+        // a peak at 20 with a tail decreasing towards 120
+        let mut levels = Vec::new();
+        for v in 20..121 {
+            for _ in 0..(121 - v) / 4 + 1 {
+                levels.push(v as u8);
+            }
+        }
+        for _ in 0..200 {
+            levels.push(20);
+        }
+        let n = levels.len() as u32;
+        let img = ImageGray::from_fn(n, 1, |x, _| Gray([levels[x as usize]]));
+        let t = triangle_threshold(&img);
+        assert!(t > 20 && t < 70, "{}", t);
+
+        // the same histogram mirrored
+        let inv = ImageGray::from_fn(n, 1, |x, _| Gray([255 - levels[x as usize]]));
+        assert_eq!(triangle_threshold(&inv), 255 - t);
+    }
+
+    #[test]
+    fn test_adaptive_threshold() {
+        // dark text on a lighting gradient that defeats a global threshold
+        let img = ImageGray::from_fn(32, 16, |x, y| {
+            let background = 60 + (x * 5) as u8;
+            Gray([if x % 8 == 3 && y > 2 && y < 13 { background - 40 } else { background }])
+        });
+        for &method in [AdaptiveMethod::Mean, AdaptiveMethod::Gaussian].iter() {
+            let out = adaptive_threshold(&img, 255.0, method, ThresholdType::BinaryInv, 5, 10.0,
+                                         BorderType::Replicate);
+            assert!(out.iter().all(|(x, y, p)| {
+                let text = x % 8 == 3 && y > 2 && y < 13;
+                p[0] == if text { 255 } else { 0 }
+            }), "{:?}", method);
+        }
+
+        let imgf = ImageGrayf::from_fn(9, 9, |x, y| {
+            Gray([if x == 4 && y == 4 { 1.0 } else { 0.0 }])
+        });
+        let out = adaptive_threshold(&imgf, 1.0, AdaptiveMethod::Mean, ThresholdType::Binary, 3,
+                                     0.0, BorderType::Reflect101);
+        assert_eq!(values(&out).iter().filter(|&&v| v == 1.0).count(), 1);
+    }
+}
@@ -9,7 +9,84 @@ use num::{ Bounded, Num, NumCast };
 /// Primitive trait from old stdlib, added max_value
 pub trait Primitive: Copy + NumCast + Num
     + PartialOrd<Self> + Clone
-    + Bounded + Display {
+    + Bounded + Display + Send + Sync + SaturateCast {
+}
+
+/// `Sync` with the `parallel` feature, where images are read from the
+/// worker threads, and implemented by every type otherwise.
+#[cfg(feature = "parallel")]
+pub trait MaybeSync: Sync {
+}
+#[cfg(feature = "parallel")]
+impl<T: Sync + ?Sized> MaybeSync for T {
+}
+
+#[cfg(not(feature = "parallel"))]
+pub trait MaybeSync {
+}
+#[cfg(not(feature = "parallel"))]
+impl<T: ?Sized> MaybeSync for T {
+}
+
+/// Conversion from `f32` that rounds integers and clamps them to the
+/// range of the type instead of failing.
+pub trait SaturateCast {
+    /// Rounds `v` to the nearest value of the type, clamped to its range.
+    fn saturate_cast(v: f32) -> Self;
+    /// Like `saturate_cast`, truncating towards zero instead of rounding.
+    fn saturate_trunc(v: f32) -> Self;
+}
+
+macro_rules! saturate_int(
+    ($($t:ty),+) => ($(
+impl SaturateCast for $t {
+    #[inline(always)]
+    fn saturate_cast(v: f32) -> $t {
+        <$t>::saturate_trunc(v.round())
+    }
+
+    #[inline(always)]
+    fn saturate_trunc(v: f32) -> $t {
+        if v.is_nan() {
+            return 0;
+        }
+        let v = v.trunc();
+        if v <= <$t>::min_value() as f32 {
+            <$t>::min_value()
+        } else if v >= <$t>::max_value() as f32 {
+            <$t>::max_value()
+        } else {
+            v as $t
+        }
+    }
+}
+    )+);
+);
+
+saturate_int!(usize, u8, u16, u32, u64, isize, i8, i16, i32, i64);
+
+impl SaturateCast for f32 {
+    #[inline(always)]
+    fn saturate_cast(v: f32) -> f32 {
+        v
+    }
+
+    #[inline(always)]
+    fn saturate_trunc(v: f32) -> f32 {
+        v
+    }
+}
+
+impl SaturateCast for f64 {
+    #[inline(always)]
+    fn saturate_cast(v: f32) -> f64 {
+        v as f64
+    }
+
+    #[inline(always)]
+    fn saturate_trunc(v: f32) -> f64 {
+        v as f64
+    }
 }
 
 impl Primitive for usize {
@@ -36,3 +113,26 @@ impl Primitive for f32 {
 }
 impl Primitive for f64 {
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_saturate_cast() {
+        assert_eq!(u8::saturate_cast(-3.0), 0);
+        assert_eq!(u8::saturate_cast(254.5), 255);
+        assert_eq!(u8::saturate_cast(300.0), 255);
+        assert_eq!(i16::saturate_cast(-1020.4), -1020);
+        assert_eq!(i16::saturate_cast(-40000.0), -32768);
+        assert_eq!(u16::saturate_cast(70000.0), 65535);
+        assert_eq!(i32::saturate_cast(::std::f32::NAN), 0);
+        assert_eq!(f32::saturate_cast(-1.25), -1.25);
+        assert_eq!(u8::saturate_trunc(254.9), 254);
+        assert_eq!(u8::saturate_trunc(-0.5), 0);
+        assert_eq!(i16::saturate_trunc(-1020.6), -1020);
+        assert_eq!(i8::saturate_trunc(300.0), 127);
+        assert_eq!(u8::saturate_trunc(::std::f32::NAN), 0);
+        assert_eq!(f32::saturate_trunc(-1.75), -1.75);
+    }
+}
@@ -5,13 +5,17 @@ use num::traits::Bounded;
 use math::utils::*;
 use math::affine::Affine2D;
 use num::traits::ToPrimitive;
+use par::for_each_row_mut;
 
 pub enum InterplateType {
     Nearest,
     Bilinear
 }
 
-pub fn resize_nearest<T: Pixel>(src: &Image<T>, width: u32, height: u32) -> Image<T> {
+pub fn resize_nearest<T, I>(src: &I, width: u32, height: u32) -> Image<T>
+    where T: Pixel,
+          I: GenericImage<Pixel=T>
+{
     let mut dst = Image::new(width, height);
     let yscale: f32 = src.height() as f32 / height as f32;
     let xscale: f32 = src.width() as f32 / width as f32;
@@ -20,18 +24,20 @@ pub fn resize_nearest<T: Pixel>(src: &Image<T>, width: u32, height: u32) -> Imag
         xidx.push(clipped_round(w as f32 * xscale, 0,
                 src.width() as i32 - 1) as usize);
     }
-    for h in 0..height {
-        let pdst = dst.row_mut(h);
+    for_each_row_mut(&mut dst, |h, pdst| {
         let psrc = src.row(clipped_round(h as f32 * yscale, 0,
                 src.height() as i32 - 1) as u32);
         for w in 0..width as usize {
             pdst[w] = psrc[xidx[w]];
         }
-    }
+    });
     dst
 }
 
-pub fn resize_bilinear<T: Pixel>(src: &Image<T>, width: u32, height: u32) -> Image<T> {
+pub fn resize_bilinear<T, I>(src: &I, width: u32, height: u32) -> Image<T>
+    where T: Pixel,
+          I: GenericImage<Pixel=T>
+{
     let mut dst = Image::new(width, height);
     let yscale: f32 = src.height() as f32 / height as f32;
     let xscale: f32 = src.width() as f32 / width as f32;
@@ -49,9 +55,7 @@ pub fn resize_bilinear<T: Pixel>(src: &Image<T>, width: u32, height: u32) -> Ima
         x_1.push(clipped_round(r, 0, src.width() as i32 - 1) as usize);
         d_0.push(d);
     }
-    for h in 0..height {
-        let pdst = dst.row_mut(h);
-
+    for_each_row_mut(&mut dst, |h, pdst| {
         let mid = h as f32 * yscale;
         let t = mid.floor();
         let b = mid.ceil();
@@ -71,21 +75,26 @@ pub fn resize_bilinear<T: Pixel>(src: &Image<T>, width: u32, height: u32) -> Ima
                 psrc1[x1],
                 d_0[w], dy);
         }
-    }
+    });
     dst
 }
 
-pub fn resize<T: Pixel>(src: &Image<T>, width: u32, height: u32, interp: InterplateType) -> Image<T> {
+pub fn resize<T, I>(src: &I, width: u32, height: u32, interp: InterplateType) -> Image<T>
+    where T: Pixel,
+          I: GenericImage<Pixel=T>
+{
     match interp {
         InterplateType::Nearest => resize_nearest(src, width, height),
         InterplateType::Bilinear => resize_bilinear(src, width, height)
     }
 }
 
-pub fn warp_perspective<T: Pixel>(src: &Image<T>, width: u32, height: u32, affine :&Affine2D, interp :InterplateType) -> Image<T> {
+pub fn warp_perspective<T, I>(src: &I, width: u32, height: u32, affine :&Affine2D, interp :InterplateType) -> Image<T>
+    where T: Pixel,
+          I: GenericImage<Pixel=T>
+{
     let mut dst: Image<T> = Image::new(width, height);
-    for h in 0..height {
-        let pdst = dst.row_mut(h);
+    for_each_row_mut(&mut dst, |h, pdst| {
         for w in 0..width {
             let coord = affine.apply_inv([w as f32, h as f32, 1f32]);
             let sx = coord[0] / coord[2];
@@ -96,7 +105,7 @@ pub fn warp_perspective<T: Pixel>(src: &Image<T>, width: u32, height: u32, affin
                     let iy = sy.round() as i32;
                     if ix >= 0 && ix < src.width() as i32 
                         && iy >= 0 && iy < src.height() as i32 {
-                            pdst[w as usize] = src[(ix as u32, iy as u32)];
+                            pdst[w as usize] = src.row(iy as u32)[ix as usize];
                         }
                 },
                 InterplateType::Bilinear => {
@@ -106,19 +115,24 @@ pub fn warp_perspective<T: Pixel>(src: &Image<T>, width: u32, height: u32, affin
                     let y0 = clip(sy.floor() as i32, 0, src.height() as i32 -1) as u32;
                     let x1 = clip(sx.ceil() as i32, 0, src.width() as i32 -1) as u32;
                     let y1 = clip(sy.ceil() as i32, 0, src.height() as i32 - 1) as u32;
-                    pdst[w as usize] = src[(x0, y0)].blend4(
-                        src[(x1, y0)],
-                        src[(x0, y1)],
-                        src[(x1, y1)],
+                    let (x0, x1) = (x0 as usize, x1 as usize);
+                    let (r0, r1) = (src.row(y0), src.row(y1));
+                    pdst[w as usize] = r0[x0].blend4(
+                        r0[x1],
+                        r1[x0],
+                        r1[x1],
                         u, v);
                 }
             }
         }
-    }
+    });
     dst
 }
 
-pub fn flip_vertical<T: Pixel>(src: &Image<T>) -> Image<T> {
+pub fn flip_vertical<T, I>(src: &I) -> Image<T>
+    where T: Pixel,
+          I: GenericImage<Pixel=T>
+{
     let mut dst = Image::new(src.width(), src.height());
     for h in 0..src.height() {
         for (a, b) in dst.row_mut(src.height() - h - 1).iter_mut().zip(src.row(h)) {
@@ -128,69 +142,84 @@ pub fn flip_vertical<T: Pixel>(src: &Image<T>) -> Image<T> {
     dst
 }
 
-pub fn flip_horizontal<T: Pixel>(src: &Image<T>) -> Image<T> {
+pub fn flip_horizontal<T, I>(src: &I) -> Image<T>
+    where T: Pixel,
+          I: GenericImage<Pixel=T>
+{
     let mut dst = Image::new(src.width(), src.height());
     for h in 0..src.height() {
-        for (a, b) in dst.row_mut(h).iter_mut().zip(src.row(h).iter().rev()) {
+        let psrc = &src.row(h)[..src.width() as usize];
+        for (a, b) in dst.row_mut(h).iter_mut().zip(psrc.iter().rev()) {
             *a = *b;
         }
     }
     dst
 }
 
-pub fn min<T, U>(src: &Image<T>) -> U
+pub fn min<T, U, I>(src: &I) -> U
     where T: Pixel,
           U: Pixel,
-          T: Index<usize, Output=U::Subpixel>
+          T: Index<usize, Output=U::Subpixel>,
+          I: GenericImage<Pixel=T>
 {
     let mut t = [U::Subpixel::max_value(); MAX_CHANNEL_COUNT];
-    for (_, _, p) in src.iter() {
-        for c in 0..T::channels() {
-            if t[c] > p[c] {
-                t[c] = p[c];
+    for y in 0..src.height() {
+        for p in src.row(y)[..src.width() as usize].iter() {
+            for c in 0..T::channels() {
+                if t[c] > p[c] {
+                    t[c] = p[c];
+                }
             }
         }
     }
     U::from_raw(&t)
 }
 
-pub fn max<T, U>(src: &Image<T>) -> U
+pub fn max<T, U, I>(src: &I) -> U
     where T: Pixel,
           U: Pixel,
-          T: Index<usize, Output=U::Subpixel>
+          T: Index<usize, Output=U::Subpixel>,
+          I: GenericImage<Pixel=T>
 {
     let mut t = [U::Subpixel::min_value(); MAX_CHANNEL_COUNT];
-    for (_, _, p) in src.iter() {
-        for c in 0..T::channels() {
-            if t[c] < p[c] {
-                t[c] = p[c];
+    for y in 0..src.height() {
+        for p in src.row(y)[..src.width() as usize].iter() {
+            for c in 0..T::channels() {
+                if t[c] < p[c] {
+                    t[c] = p[c];
+                }
             }
         }
     }
     U::from_raw(&t)
 }
 
-pub fn normalize<U, V, M>(src: &Image<U>, alpha: f32, beta: f32) -> Image<V> 
+pub fn normalize<U, V, M, I>(src: &I, alpha: f32, beta: f32) -> Image<V> 
     where U: Pixel,
           V: Pixel,
           M: Pixel,
-          U: Index<usize, Output=M::Subpixel>
+          U: Index<usize, Output=M::Subpixel>,
+          I: GenericImage<Pixel=U>
 {
     let mut dst = Image::<V>::new(src.width(), src.height());
     let mut mins = [0f32; MAX_CHANNEL_COUNT];
     let mut maxs = [0f32; MAX_CHANNEL_COUNT];
-    let min_p: M = min(&src);
-    let max_p: M = max(&src);
+    let min_p: M = min(src);
+    let max_p: M = max(src);
     let s = beta - alpha;
     for c in 0..U::channels() {
         mins[c] = min_p.raw()[c].to_f32().unwrap();
         maxs[c] = max_p.raw()[c].to_f32().unwrap();
     }
-    for ((_, _, p), (_, _, q)) in dst.iter_mut().zip(src.iter()) {
-        for c in 0..U::channels() {
-            let d = maxs[c] - mins[c];
-            let t = (q.raw()[c].to_f32().unwrap() - mins[c]) / d * s + alpha;
-            p.raw_mut()[c] = NumCast::from(t).unwrap();
+    for y in 0..src.height() {
+        let psrc = src.row(y);
+        let pdst = dst.row_mut(y);
+        for x in 0..src.width() as usize {
+            for c in 0..U::channels() {
+                let d = maxs[c] - mins[c];
+                let t = (psrc[x].raw()[c].to_f32().unwrap() - mins[c]) / d * s + alpha;
+                pdst[x].raw_mut()[c] = NumCast::from(t).unwrap();
+            }
         }
     }
     dst
@@ -207,7 +236,10 @@ pub enum RotateType{
     Cw270
 }
 
-pub fn rotate_cw90<T: Pixel>(src: &Image<T>) -> Image<T> {
+pub fn rotate_cw90<T, I>(src: &I) -> Image<T>
+    where T: Pixel,
+          I: GenericImage<Pixel=T>
+{
     let mut dst = Image::new(src.height(), src.width());
     for h in 0..src.height() {
         let psrc = src.row(h);
@@ -219,7 +251,10 @@ pub fn rotate_cw90<T: Pixel>(src: &Image<T>) -> Image<T> {
     dst
 }
 
-pub fn rotate_cw180<T: Pixel>(src: &Image<T>) -> Image<T> {
+pub fn rotate_cw180<T, I>(src: &I) -> Image<T>
+    where T: Pixel,
+          I: GenericImage<Pixel=T>
+{
     let mut dst = Image::new(src.width(), src.height());
     for h in 0..src.height() {
         let psrc = src.row(h);
@@ -232,7 +267,10 @@ pub fn rotate_cw180<T: Pixel>(src: &Image<T>) -> Image<T> {
     dst
 }
 
-pub fn rotate_cw270<T: Pixel>(src: &Image<T>) -> Image<T> {
+pub fn rotate_cw270<T, I>(src: &I) -> Image<T>
+    where T: Pixel,
+          I: GenericImage<Pixel=T>
+{
     let mut dst = Image::new(src.height(), src.width());
     for h in 0..src.height() {
         let psrc = src.row(h);
@@ -243,9 +281,12 @@ pub fn rotate_cw270<T: Pixel>(src: &Image<T>) -> Image<T> {
     dst
 }
 
-pub fn rotate<T: Pixel>(src: &Image<T>, rtype: RotateType) -> Image<T> {
+pub fn rotate<T, I>(src: &I, rtype: RotateType) -> Image<T>
+    where T: Pixel,
+          I: GenericImage<Pixel=T>
+{
     match rtype {
-        RotateType::Cw0 => src.clone(),
+        RotateType::Cw0 => src.to_image(),
         RotateType::Cw90 => rotate_cw90(src),
         RotateType::Cw180 => rotate_cw180(src),
         RotateType::Cw270 => rotate_cw270(src)
@@ -256,7 +297,7 @@ pub fn rotate<T: Pixel>(src: &Image<T>, rtype: RotateType) -> Image<T> {
 mod test {
     use super::*;
     use std::path::Path;
-    use image::ImageBgra;
+    use image::*;
     use imageio::ImageIO;
     use imageio::FreeImageIO;
     use geo::*;
@@ -328,5 +369,26 @@ mod test {
         let target = Path::new("/tmp/test-rotate-out3.jpg");
         FreeImageIO::save(&target, &out).unwrap();
     }
+
+    #[test]
+    fn test_view() {
+        let mut img = ImageGray::new(6, 6);
+        for (x, y, p) in img.iter_mut() {
+            *p = Gray([(y * 6 + x) as u8]);
+        }
+        let v = img.view(&Recti::new(1, 1, 3, 2));
+
+        let out = flip_horizontal(&v);
+        assert_eq!(out.row(0), &[Gray([9]), Gray([8]), Gray([7])]);
+        let out = rotate_cw90(&v);
+        assert_eq!(out.size(), (2, 3));
+        assert_eq!(out.row(0), &[Gray([13]), Gray([7])]);
+        let out = resize_nearest(&v, 6, 4);
+        assert_eq!(out[(0, 0)], Gray([7]));
+        assert_eq!(out[(5, 3)], Gray([15]));
+        let lo: Gray<u8> = min(&v);
+        let hi: Gray<u8> = max(&v);
+        assert_eq!((lo, hi), (Gray([7]), Gray([15])));
+    }
 }
 
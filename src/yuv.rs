@@ -0,0 +1,428 @@
+//! YUV frames as produced by cameras and video decoders.
+//!
+//! A `YuvFrame` owns one to three 8-bit planes, each an `ImageGray` with
+//! its own stride:
+//!
+//! * `I420`: Y, U and V planes, chroma subsampled 2x2
+//! * `Nv12` / `Nv21`: Y plane and one interleaved UV / VU plane, 2x2
+//! * `Yuyv`: a single packed plane, `Y0 U Y1 V` per pixel pair
+
+use image::*;
+use geo::Recti;
+use traits::SaturateCast;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum YuvFormat {
+    I420,
+    Nv12,
+    Nv21,
+    Yuyv
+}
+
+/// The luma/chroma matrix.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum YuvStandard {
+    Bt601,
+    Bt709
+}
+
+/// `Limited` maps Y to [16, 235] and chroma to [16, 240], `Full` uses
+/// [0, 255] for all planes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum YuvRange {
+    Full,
+    Limited
+}
+
+struct Coeffs {
+    kr: f32,
+    kb: f32,
+    yoff: f32,
+    yscale: f32,
+    cscale: f32
+}
+
+impl Coeffs {
+    fn new(standard: YuvStandard, range: YuvRange) -> Coeffs {
+        let (kr, kb) = match standard {
+            YuvStandard::Bt601 => (0.299, 0.114),
+            YuvStandard::Bt709 => (0.2126, 0.0722)
+        };
+        let (yoff, yscale, cscale) = match range {
+            YuvRange::Full => (0.0, 1.0, 1.0),
+            YuvRange::Limited => (16.0, 219.0 / 255.0, 224.0 / 255.0)
+        };
+        Coeffs {
+            kr: kr,
+            kb: kb,
+            yoff: yoff,
+            yscale: yscale,
+            cscale: cscale
+        }
+    }
+
+    #[inline]
+    fn luma(&self, b: f32, g: f32, r: f32) -> f32 {
+        self.kr * r + (1.0 - self.kr - self.kb) * g + self.kb * b
+    }
+
+    #[inline]
+    fn y(&self, b: f32, g: f32, r: f32) -> u8 {
+        u8::saturate_cast(self.yoff + self.yscale * self.luma(b, g, r))
+    }
+
+    #[inline]
+    fn uv(&self, b: f32, g: f32, r: f32) -> (u8, u8) {
+        let y = self.luma(b, g, r);
+        let u = 128.0 + self.cscale * (b - y) / (2.0 * (1.0 - self.kb));
+        let v = 128.0 + self.cscale * (r - y) / (2.0 * (1.0 - self.kr));
+        (u8::saturate_cast(u), u8::saturate_cast(v))
+    }
+
+    #[inline]
+    fn bgr(&self, y: u8, u: u8, v: u8) -> [u8; 3] {
+        let y = (y as f32 - self.yoff) / self.yscale;
+        let u = (u as f32 - 128.0) / self.cscale;
+        let v = (v as f32 - 128.0) / self.cscale;
+        let r = y + 2.0 * (1.0 - self.kr) * v;
+        let b = y + 2.0 * (1.0 - self.kb) * u;
+        let g = (y - self.kr * r - self.kb * b) / (1.0 - self.kr - self.kb);
+        [u8::saturate_cast(b), u8::saturate_cast(g), u8::saturate_cast(r)]
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct YuvFrame {
+    format: YuvFormat,
+    w: u32,
+    h: u32,
+    planes: Vec<ImageGray>
+}
+
+// (width, height) of every plane, in bytes and rows
+fn plane_sizes(format: YuvFormat, w: u32, h: u32) -> Vec<(u32, u32)> {
+    let (w2, h2) = ((w + 1) / 2, (h + 1) / 2);
+    match format {
+        YuvFormat::I420 => vec![(w, h), (w2, h2), (w2, h2)],
+        YuvFormat::Nv12 | YuvFormat::Nv21 => vec![(w, h), (2 * w2, h2)],
+        YuvFormat::Yuyv => vec![(4 * w2, h)]
+    }
+}
+
+impl YuvFrame {
+    /// Allocates a frame that is black in `range`: Y is 0 in full range and
+    /// 16 in limited range, chroma is 128.
+    pub fn new(format: YuvFormat, width: u32, height: u32, range: YuvRange) -> YuvFrame {
+        let black = Gray([Coeffs::new(YuvStandard::Bt601, range).y(0.0, 0.0, 0.0)]);
+        let mut planes: Vec<ImageGray> = plane_sizes(format, width, height).iter()
+            .map(|&(w, h)| Image::from_pixel(w, h, Gray([128])))
+            .collect();
+        match format {
+            YuvFormat::Yuyv => {
+                for y in 0..height {
+                    for (i, p) in planes[0].row_mut(y).iter_mut().enumerate() {
+                        if i % 2 == 0 {
+                            *p = black;
+                        }
+                    }
+                }
+            },
+            _ => planes[0].fill(&black)
+        }
+        YuvFrame {
+            format: format,
+            w: width,
+            h: height,
+            planes: planes
+        }
+    }
+
+    /// Wraps existing planes, e.g. built with `Image::from_vec` over a
+    /// decoder's buffers. Fails with `InvalidImage` if the number or the
+    /// sizes of the planes do not match `format`.
+    pub fn from_planes(format: YuvFormat, width: u32, height: u32, planes: Vec<ImageGray>)
+        -> Result<YuvFrame, ImageError> {
+        let sizes = plane_sizes(format, width, height);
+        if planes.len() != sizes.len() {
+            return Err(ImageError::InvalidImage);
+        }
+        for (p, s) in planes.iter().zip(sizes.iter()) {
+            if p.size() != *s {
+                return Err(ImageError::InvalidImage);
+            }
+        }
+        Ok(YuvFrame {
+            format: format,
+            w: width,
+            h: height,
+            planes: planes
+        })
+    }
+
+    #[inline]
+    pub fn format(&self) -> YuvFormat { self.format }
+
+    #[inline]
+    pub fn width(&self) -> u32 { self.w }
+
+    #[inline]
+    pub fn height(&self) -> u32 { self.h }
+
+    #[inline]
+    pub fn planes(&self) -> &[ImageGray] {
+        &self.planes
+    }
+
+    #[inline]
+    pub fn planes_mut(&mut self) -> &mut [ImageGray] {
+        &mut self.planes
+    }
+
+    pub fn into_planes(self) -> Vec<ImageGray> {
+        self.planes
+    }
+
+    /// Borrows the luma plane, `None` for packed formats.
+    pub fn y_plane(&self) -> Option<ImageView<Gray<u8>>> {
+        match self.format {
+            YuvFormat::Yuyv => None,
+            _ => Some(self.planes[0].view(&Recti::new(0, 0, self.w as i32, self.h as i32)))
+        }
+    }
+
+    // (y, u, v) of pixel (x, y)
+    #[inline]
+    fn sample(&self, x: usize, y: u32) -> (u8, u8, u8) {
+        let p = &self.planes;
+        match self.format {
+            YuvFormat::I420 => {
+                (p[0].row(y)[x][0], p[1].row(y / 2)[x / 2][0], p[2].row(y / 2)[x / 2][0])
+            },
+            YuvFormat::Nv12 => {
+                let uv = p[1].row(y / 2);
+                (p[0].row(y)[x][0], uv[x / 2 * 2][0], uv[x / 2 * 2 + 1][0])
+            },
+            YuvFormat::Nv21 => {
+                let vu = p[1].row(y / 2);
+                (p[0].row(y)[x][0], vu[x / 2 * 2 + 1][0], vu[x / 2 * 2][0])
+            },
+            YuvFormat::Yuyv => {
+                let r = p[0].row(y);
+                (r[2 * x][0], r[x / 2 * 4 + 1][0], r[x / 2 * 4 + 3][0])
+            }
+        }
+    }
+
+    #[inline]
+    fn set_y(&mut self, x: usize, y: u32, v: u8) {
+        match self.format {
+            YuvFormat::Yuyv => self.planes[0].row_mut(y)[2 * x] = Gray([v]),
+            _ => self.planes[0].row_mut(y)[x] = Gray([v])
+        }
+    }
+
+    // (cx, cy) is in chroma samples
+    #[inline]
+    fn set_uv(&mut self, cx: usize, cy: u32, u: u8, v: u8) {
+        let p = &mut self.planes;
+        match self.format {
+            YuvFormat::I420 => {
+                p[1].row_mut(cy)[cx] = Gray([u]);
+                p[2].row_mut(cy)[cx] = Gray([v]);
+            },
+            YuvFormat::Nv12 => {
+                let uv = p[1].row_mut(cy);
+                uv[2 * cx] = Gray([u]);
+                uv[2 * cx + 1] = Gray([v]);
+            },
+            YuvFormat::Nv21 => {
+                let vu = p[1].row_mut(cy);
+                vu[2 * cx] = Gray([v]);
+                vu[2 * cx + 1] = Gray([u]);
+            },
+            YuvFormat::Yuyv => {
+                let r = p[0].row_mut(cy);
+                r[4 * cx + 1] = Gray([u]);
+                r[4 * cx + 3] = Gray([v]);
+            }
+        }
+    }
+
+    /// Converts a BGR(A) image, chroma is taken from the average color of
+    /// each subsampled block.
+    pub fn from_rgb<P, I>(src: &I, format: YuvFormat, standard: YuvStandard, range: YuvRange)
+        -> YuvFrame
+        where P: RGBPixel<Subpixel=u8>,
+              I: GenericImage<Pixel=P>
+    {
+        let (w, h) = src.size();
+        let c = Coeffs::new(standard, range);
+        let mut frame = YuvFrame::new(format, w, h, range);
+        let (bi, gi, ri) = (P::blue_index(), P::green_index(), P::red_index());
+        for y in 0..h {
+            let psrc = src.row(y);
+            for x in 0..w as usize {
+                let p = psrc[x].raw();
+                let l = c.y(p[bi] as f32, p[gi] as f32, p[ri] as f32);
+                frame.set_y(x, y, l);
+            }
+        }
+        let (cw, ch, sy) = match format {
+            YuvFormat::Yuyv => ((w + 1) / 2, h, 1),
+            _ => ((w + 1) / 2, (h + 1) / 2, 2)
+        };
+        for cy in 0..ch {
+            for cx in 0..cw {
+                let (mut b, mut g, mut r, mut n) = (0f32, 0f32, 0f32, 0f32);
+                for y in cy * sy..(cy * sy + sy).min(h) {
+                    let psrc = src.row(y);
+                    for x in 2 * cx..(2 * cx + 2).min(w) {
+                        let p = psrc[x as usize].raw();
+                        b += p[bi] as f32;
+                        g += p[gi] as f32;
+                        r += p[ri] as f32;
+                        n += 1.0;
+                    }
+                }
+                let (u, v) = c.uv(b / n, g / n, r / n);
+                frame.set_uv(cx as usize, cy, u, v);
+            }
+        }
+        frame
+    }
+
+    pub fn from_bgr<I>(src: &I, format: YuvFormat, standard: YuvStandard, range: YuvRange)
+        -> YuvFrame
+        where I: GenericImage<Pixel=Bgr<u8>>
+    {
+        YuvFrame::from_rgb(src, format, standard, range)
+    }
+
+    pub fn from_bgra<I>(src: &I, format: YuvFormat, standard: YuvStandard, range: YuvRange)
+        -> YuvFrame
+        where I: GenericImage<Pixel=Bgra<u8>>
+    {
+        YuvFrame::from_rgb(src, format, standard, range)
+    }
+
+    /// Converts to a BGR(A) image, alpha is set to opaque.
+    pub fn to_rgb<P>(&self, standard: YuvStandard, range: YuvRange) -> Image<P>
+        where P: RGBPixel<Subpixel=u8>
+    {
+        let c = Coeffs::new(standard, range);
+        let (bi, gi, ri) = (P::blue_index(), P::green_index(), P::red_index());
+        let mut opaque = P::zero();
+        for ch in opaque.raw_mut().iter_mut() {
+            *ch = 255;
+        }
+        let mut dst = Image::from_pixel(self.w, self.h, opaque);
+        for y in 0..self.h {
+            for x in 0..self.w as usize {
+                let (l, u, v) = self.sample(x, y);
+                let bgr = c.bgr(l, u, v);
+                let p = dst.row_mut(y)[x].raw_mut();
+                p[bi] = bgr[0];
+                p[gi] = bgr[1];
+                p[ri] = bgr[2];
+            }
+        }
+        dst
+    }
+
+    pub fn to_bgr(&self, standard: YuvStandard, range: YuvRange) -> ImageBgr {
+        self.to_rgb(standard, range)
+    }
+
+    pub fn to_bgra(&self, standard: YuvStandard, range: YuvRange) -> ImageBgra {
+        self.to_rgb(standard, range)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use image::*;
+    use super::*;
+
+    fn blocks() -> ImageBgr {
+        // 2x2 blocks of constant color, so chroma subsampling is lossless
+        let colors = [Bgr([255, 255, 255]), Bgr([0, 0, 0]), Bgr([40, 200, 90]),
+                      Bgr([220, 30, 60]), Bgr([128, 128, 128]), Bgr([0, 0, 255])];
+        Image::from_fn(6, 4, |x, y| colors[((y / 2) * 3 + x / 2) as usize % 6])
+    }
+
+    fn close(a: &ImageBgr, b: &ImageBgr, eps: i32) -> bool {
+        a.iter().zip(b.iter()).all(|((_, _, p), (_, _, q))| {
+            (0..3).all(|c| (p[c] as i32 - q[c] as i32).abs() <= eps)
+        })
+    }
+
+    #[test]
+    fn test_levels() {
+        let white = ImageBgr::from_pixel(2, 2, Bgr([255, 255, 255]));
+        let black = ImageBgr::from_pixel(2, 2, Bgr([0, 0, 0]));
+        let f = YuvFrame::from_bgr(&white, YuvFormat::I420, YuvStandard::Bt601, YuvRange::Limited);
+        assert_eq!(f.planes()[0][(0, 0)], Gray([235]));
+        assert_eq!(f.planes()[1][(0, 0)], Gray([128]));
+        assert_eq!(f.planes()[2][(0, 0)], Gray([128]));
+        let f = YuvFrame::from_bgr(&black, YuvFormat::I420, YuvStandard::Bt709, YuvRange::Limited);
+        assert_eq!(f.planes()[0][(1, 1)], Gray([16]));
+        let f = YuvFrame::from_bgr(&white, YuvFormat::Nv12, YuvStandard::Bt709, YuvRange::Full);
+        assert_eq!(f.planes()[0][(1, 1)], Gray([255]));
+
+        let red = ImageBgr::from_pixel(2, 2, Bgr([0, 0, 255]));
+        let f = YuvFrame::from_bgr(&red, YuvFormat::Nv12, YuvStandard::Bt601, YuvRange::Full);
+        assert_eq!(f.planes()[1].row(0), &[Gray([85]), Gray([255])]);
+        let f = YuvFrame::from_bgr(&red, YuvFormat::Nv21, YuvStandard::Bt601, YuvRange::Full);
+        assert_eq!(f.planes()[1].row(0), &[Gray([255]), Gray([85])]);
+        let f = YuvFrame::from_bgr(&red, YuvFormat::Yuyv, YuvStandard::Bt601, YuvRange::Full);
+        assert_eq!(f.planes()[0].row(1), &[Gray([76]), Gray([85]), Gray([76]), Gray([255])]);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let src = blocks();
+        for &format in [YuvFormat::I420, YuvFormat::Nv12,
+                        YuvFormat::Nv21, YuvFormat::Yuyv].iter() {
+            for &standard in [YuvStandard::Bt601, YuvStandard::Bt709].iter() {
+                for &range in [YuvRange::Full, YuvRange::Limited].iter() {
+                    let f = YuvFrame::from_bgr(&src, format, standard, range);
+                    assert_eq!((f.width(), f.height()), (6, 4));
+                    let out = f.to_bgr(standard, range);
+                    assert!(close(&src, &out, 3), "{:?} {:?} {:?}", format, standard, range);
+                    let out = f.to_bgra(standard, range);
+                    assert!(out.iter().all(|(_, _, p)| p[3] == 255));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_planes() {
+        let f = YuvFrame::new(YuvFormat::I420, 5, 3, YuvRange::Full);
+        let sizes: Vec<(u32, u32)> = f.planes().iter().map(|p| p.size()).collect();
+        assert_eq!(sizes, vec![(5, 3), (3, 2), (3, 2)]);
+        assert_eq!(f.to_bgr(YuvStandard::Bt601, YuvRange::Full)[(4, 2)], Bgr([0, 0, 0]));
+        for &format in [YuvFormat::I420, YuvFormat::Nv12, YuvFormat::Yuyv].iter() {
+            let f = YuvFrame::new(format, 5, 3, YuvRange::Limited);
+            assert_eq!(f.planes()[0][(2, 2)], Gray([16]));
+            let out = f.to_bgr(YuvStandard::Bt709, YuvRange::Limited);
+            assert!(out.iter().all(|(_, _, p)| *p == Bgr([0, 0, 0])), "{:?}", format);
+        }
+
+        let f = YuvFrame::from_bgr(&blocks(), YuvFormat::Nv12, YuvStandard::Bt601, YuvRange::Full);
+        let y = f.y_plane().unwrap();
+        assert_eq!(y.size(), (6, 4));
+        assert_eq!(y[(0, 0)], Gray([255]));
+        assert!(YuvFrame::new(YuvFormat::Yuyv, 4, 4, YuvRange::Full).y_plane().is_none());
+
+        // padded strides as handed out by decoders
+        let luma = ImageGray::from_vec(4, 2, 8, vec![Gray([100]); 16]).unwrap();
+        let chroma = ImageGray::from_vec(4, 1, 8, vec![Gray([128]); 8]).unwrap();
+        let f = YuvFrame::from_planes(YuvFormat::Nv12, 4, 2, vec![luma, chroma]).unwrap();
+        let out = f.to_bgr(YuvStandard::Bt601, YuvRange::Full);
+        assert!(out.iter().all(|(_, _, p)| *p == Bgr([100, 100, 100])));
+
+        let luma = ImageGray::new(4, 2);
+        assert!(YuvFrame::from_planes(YuvFormat::I420, 4, 2, vec![luma]).is_err());
+    }
+}